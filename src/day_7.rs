@@ -84,102 +84,166 @@ Here are some example programs:
 Try every combination of the new phase settings on the amplifier feedback loop. What is the highest signal that can be sent to the thrusters?
 
 */
-use itertools::Itertools;
 use std::convert::TryFrom;
 use std::fs::File;
 use std::io::{BufReader, Read};
 
-use crate::int_code::{IntComputer, IntComputerState, ValueType};
+use crate::int_code::{IntComputer, PhaseSearch, Pipeline, PipelineReport, Trial, ValueType};
 // use int_code::IntComputer;
 
-pub fn day_7_run_part_1() {
-    let mut reader = BufReader::new(File::open("input_day7_part1.txt").expect("File not found"));
-    let mut program = String::new();
+/// How the amplifiers are wired.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wiring {
+    /// A to E in a row with phases 0 to 4.
+    Series,
+    /// E feeding back into A with phases 5 to 9.
+    Feedback,
+}
 
-    reader.read_to_string(&mut program).unwrap();
-    let phase_settings = (0..=4).permutations(5);
-    // let iter = phase_settings.as_slice().iter().permutations();
-    let mut results = Vec::new();
-    for perm in phase_settings {
-        let mut signal = 0;
-        for setting in perm {
-            let mut int_computer: IntComputer = IntComputer::try_from(program.as_str()).unwrap();
-            int_computer.push_input(setting);
-            int_computer.push_input(signal);
-            int_computer.run().unwrap();
-            signal = int_computer.get_output().unwrap();
-        }
-        results.push(signal);
-    }
-    println!("Part1: Found {} results", results.len());
-    println!("Part1: Maximum is {}", results.iter().max().unwrap());
+/// The strongest signal found and the phase order giving it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AmpResult {
+    pub signal: ValueType,
+    pub phases: Vec<ValueType>,
+    /// Outputs of each amplifier for the winning order, in feedback mode how
+    /// often the signal went around the loop.
+    pub iterations: Vec<u64>,
+    /// Number of permutations tried.
+    pub tried: u64,
+    /// Signal and phase order of every permutation, strongest first, if
+    /// asked for.
+    pub ranking: Option<Vec<(ValueType, Vec<ValueType>)>>,
 }
 
-pub fn day_7_run_part_2() {
-    let mut reader = BufReader::new(File::open("input_day7_part1.txt").expect("File not found"));
-    let mut program = String::new();
+/// Tries every permutation of the phases and returns the best one. Fails
+/// with the first permutation whose amplifiers failed or gave no signal,
+/// its `error` tells which.
+pub fn best_phase_setting(
+    program: &IntComputer,
+    wiring: Wiring,
+) -> Result<AmpResult, Trial<ValueType>> {
+    search_phases(program, wiring, false)
+}
 
-    reader.read_to_string(&mut program).unwrap();
-    let phase_settings = (5..=9).permutations(5);
-    let int_computer: IntComputer = IntComputer::try_from(program.as_str()).unwrap();
-    let mut best = 0;
-    for input in phase_settings {
-        let result = run_amp_simulation(&int_computer, &input);
-        if result > best {
-            println!("New Best found! {} > {}", result, best);
-            best = result;
-        }
+/// Like `best_phase_setting`, also ranking all permutations.
+pub fn ranked_phase_settings(
+    program: &IntComputer,
+    wiring: Wiring,
+) -> Result<AmpResult, Trial<ValueType>> {
+    search_phases(program, wiring, true)
+}
+
+fn search_phases(
+    program: &IntComputer,
+    wiring: Wiring,
+    ranked: bool,
+) -> Result<AmpResult, Trial<ValueType>> {
+    let search = match wiring {
+        Wiring::Series => PhaseSearch::series(program, &[0, 1, 2, 3, 4]),
+        Wiring::Feedback => PhaseSearch::ring(program, &[5, 6, 7, 8, 9]),
+    };
+    // only a ranking needs every trial kept
+    let (report, trials) = if ranked {
+        let (report, trials) = search.trials(0).expect("120 orderings");
+        (report, Some(trials))
+    } else {
+        (search.run(0).expect("120 orderings"), None)
+    };
+    if let Some(trial) = report.failures.into_iter().next() {
+        return Err(trial);
     }
-    println!("Best Setting is {}", best);
-    // println!("Part2: Found {} results", results.len());
-    // println!("Part2: Maximum is {}", results.iter().max().unwrap());
+    let best = report.best.expect("no permutations to try");
+    let ranking = trials.map(|trials| {
+        let mut table: Vec<_> = trials
+            .into_iter()
+            .map(|t| (t.signal.unwrap(), t.phases))
+            .collect();
+        // stable, ties stay in permutation order
+        table.sort_by_key(|&(signal, _)| std::cmp::Reverse(signal));
+        table
+    });
+    Ok(AmpResult {
+        signal: best.signal.unwrap(),
+        phases: best.phases,
+        iterations: best.outputs,
+        tried: report.tried,
+        ranking,
+    })
 }
 
-fn run_amp_simulation(program: &IntComputer, setting: &[ValueType]) -> ValueType {
-    let mut amps: Vec<IntComputer> = Vec::new();
-    // create a list of computers, each with it's own state / separate copy
-    // and set the first input, phase setting
-    for &x in setting.iter() {
-        let mut a = program.clone();
-        a.push_input(x);
-        amps.push(a);
+/// What went wrong with a failed permutation.
+fn failure(trial: &Trial<ValueType>) -> String {
+    match &trial.error {
+        Some(e) => format!("amplifiers {:?} failed: {}", trial.phases, e),
+        None => format!("amplifiers {:?} produced no signal", trial.phases),
     }
+}
 
-    let mut signal = 0;
-    let mut cnt = 0;
+fn read_program() -> IntComputer {
+    let mut reader = BufReader::new(File::open("input_day7_part1.txt").expect("File not found"));
+    let mut program = String::new();
 
-    loop {
-        for amp in amps.iter_mut() {
-            amp.push_input(signal);
-            amp.run().unwrap();
-            signal = amp.get_output().unwrap();
-        }
-        cnt += 1;
-        if amps
-            .iter()
-            .all(|x| x.get_state() == IntComputerState::Stopped)
-        {
+    reader.read_to_string(&mut program).unwrap();
+    IntComputer::try_from(program.as_str()).unwrap()
+}
+
+pub fn day_7_run_part_1() {
+    match best_phase_setting(&read_program(), Wiring::Series) {
+        Ok(result) => {
+            println!("Part1: Found {} results", result.tried);
             println!(
-                "Input: {:?} Signal strength {}, iterations {}",
-                setting, signal, cnt
+                "Part1: Maximum is {} from {:?}",
+                result.signal, result.phases
             );
-            break;
         }
+        Err(trial) => println!("Part1: {}", failure(&trial)),
     }
+}
 
-    signal
+pub fn day_7_run_part_2() {
+    let program = read_program();
+    let result = match best_phase_setting(&program, Wiring::Feedback) {
+        Ok(result) => result,
+        Err(trial) => {
+            println!("Part2: {}", failure(&trial));
+            return;
+        }
+    };
+    println!(
+        "Best Setting is {} from {:?}, iterations {:?}",
+        result.signal, result.phases, result.iterations
+    );
+    let report = run_amp_simulation(&program, &result.phases);
+    for (amp, stage) in report.stages.iter().enumerate() {
+        println!(
+            "Amplifier {} phase {}: {:?} after {} steps, {} outputs in {:?}",
+            amp, stage.phase, stage.result, stage.steps, stage.outputs, stage.elapsed
+        );
+    }
+}
+
+/// Runs the amplifiers in a feedback loop, one thread each with E feeding
+/// back into A until they all halt, and reports how each of them ended.
+pub fn run_amp_simulation(
+    program: &IntComputer,
+    setting: &[ValueType],
+) -> PipelineReport<ValueType> {
+    Pipeline::ring(program, setting).run(0)
 }
 
 #[cfg(test)]
+#[allow(non_snake_case)]
 mod Test {
     use super::*;
+    use crate::int_code::IntcodeError;
     #[test]
     fn test_day_2_1() {
         let input = "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5";
         let program = IntComputer::try_from(input).unwrap();
 
-        let result = run_amp_simulation(&program, &[9,8,7,6,5]);
-        assert_eq!(result, 139629729);
+        let report = run_amp_simulation(&program, &[9,8,7,6,5]);
+        assert_eq!(report.signal, Some(139629729));
+        assert!(report.is_complete());
     }
 
     #[test]
@@ -187,7 +251,53 @@ mod Test {
         let input = "3,52,1001,52,-5,52,3,53,1,52,56,54,1007,54,5,55,1005,55,26,1001,54,-5,54,1105,1,12,1,53,54,53,1008,54,0,55,1001,55,1,55,2,53,55,53,4,53,1001,56,-1,56,1005,56,6,99,0,0,0,0,10";
         let program = IntComputer::try_from(input).unwrap();
 
-        let result = run_amp_simulation(&program, &[9,7,8,5,6]);
-        assert_eq!(result, 18216);
+        let report = run_amp_simulation(&program, &[9,7,8,5,6]);
+        assert_eq!(report.signal, Some(18216));
+        assert!(report.stages.iter().all(|s| s.outputs == 10));
+    }
+
+    #[test]
+    fn test_best_series() {
+        let input = "3,31,3,32,1002,32,10,32,1001,31,-2,31,1007,31,0,33,1002,33,7,33,1,33,31,31,1,32,31,31,4,31,99,0,0,0";
+        let program = IntComputer::try_from(input).unwrap();
+
+        let result = ranked_phase_settings(&program, Wiring::Series).unwrap();
+        assert_eq!(result.signal, 65210);
+        assert_eq!(result.phases, vec![1, 0, 4, 3, 2]);
+        assert_eq!(result.iterations, vec![1; 5]);
+        let ranking = result.ranking.unwrap();
+        assert_eq!(ranking.len(), 120);
+        assert_eq!(ranking[0], (65210, vec![1, 0, 4, 3, 2]));
+        assert!(ranking.windows(2).all(|w| w[0].0 >= w[1].0));
+    }
+
+    #[test]
+    fn test_best_feedback() {
+        let input = "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5";
+        let program = IntComputer::try_from(input).unwrap();
+
+        let result = best_phase_setting(&program, Wiring::Feedback).unwrap();
+        assert_eq!(result.signal, 139629729);
+        assert_eq!(result.phases, vec![9, 8, 7, 6, 5]);
+        assert_eq!(result.iterations, vec![5; 5]);
+        assert_eq!(result.tried, 120);
+        assert!(result.ranking.is_none());
+    }
+
+    #[test]
+    fn test_failing_amplifiers() {
+        // takes its phase and halts without an output
+        let program = IntComputer::try_from("3,0,99").unwrap();
+        let trial = best_phase_setting(&program, Wiring::Series).unwrap_err();
+        assert_eq!(trial.phases, vec![0, 1, 2, 3, 4]);
+        assert!(trial.error.is_none());
+
+        let program = IntComputer::try_from("98").unwrap();
+        let trial = best_phase_setting(&program, Wiring::Feedback).unwrap_err();
+        assert_eq!(trial.phases, vec![5, 6, 7, 8, 9]);
+        assert!(matches!(
+            trial.error.unwrap().error,
+            IntcodeError::InvalidOpcode { pc: 0, .. }
+        ));
     }
 }
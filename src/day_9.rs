@@ -1,4 +1,4 @@
-use crate::int_code::{IntComputer, IntComputerState, ValueType};
+use crate::int_code::IntComputer;
 use std::convert::TryFrom;
 use std::io::{prelude::*, BufReader};
 use std::fs::File;
@@ -34,8 +34,10 @@ pub fn day_9_run_part2() -> std::io::Result<()> {
 }
 
 #[cfg(test)]
+#[allow(non_snake_case)]
 mod Test {
     use super::*;
+    use crate::int_code::ValueType;
     #[test]
     fn test_day_9_quine() {
         let input = "109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99";
@@ -1,12 +1,63 @@
 use std::collections::VecDeque;
-use itertools::zip;
 use std::convert::TryFrom;
+use std::str::FromStr;
+use std::time::Instant;
 
-pub fn parse_program(input: &str) -> Result<Vec<ValueType>, std::num::ParseIntError> {
+pub mod asm;
+pub mod cfg;
+mod coverage;
+mod custom;
+pub mod debugger;
+pub mod disasm;
+mod error;
+mod history;
+mod io;
+mod limits;
+mod memory;
+pub mod network;
+mod overflow;
+pub mod pipeline;
+mod profile;
+pub mod search;
+mod smc;
+pub mod snapshot;
+pub mod trace;
+mod validation;
+mod value;
+
+pub use asm::assemble;
+pub use cfg::control_flow;
+pub use coverage::{Coverage, Usage};
+pub use custom::{Access, CustomCall, CustomOp};
+pub use disasm::disassemble;
+pub use error::IntcodeError;
+pub use history::{Delta, GotoError};
+pub use io::{
+    AsciiInput, AsciiOutput, FnInput, FnOutput, InputSource, IterInput, OutputSink, SharedQueue,
+};
+pub use limits::{Limit, Limits};
+pub use memory::{Memory, PagedMemory, VEC_CAPACITY};
+pub use network::{Activity, Network, NodeError, Packet};
+pub use overflow::OverflowPolicy;
+pub use pipeline::{Pipeline, PipelineReport, StageReport};
+pub use profile::{HotSpot, Profile};
+pub use search::{PhaseSearch, SearchReport, TooManyOrderings, Trial};
+pub use smc::SmcEvent;
+pub use snapshot::Snapshot;
+pub use trace::{TraceEvent, TraceSink};
+pub use validation::Validation;
+pub use value::{Promoting, Value, WideValue};
+
+use custom::CustomOps;
+use history::History;
+use smc::SmcDetector;
+use trace::Tracer;
+
+pub fn parse_program<V: FromStr>(input: &str) -> Result<Vec<V>, V::Err> {
     input.trim().split(',').map(|s| s.parse()).collect()
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Opcode {
     Add,
     Mult,
@@ -18,13 +69,13 @@ pub enum Opcode {
     LessThan,
     Equals,
     SetRel,
-    Err,
+    /// Registered with `IntComputer::register_op`, taking `arity` parameters.
+    Custom { code: u8, arity: u8 },
 }
 
 pub type ValueType = i64;
-type Memory = Vec<ValueType>;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Param {
     Pos,
     Imm,
@@ -32,6 +83,57 @@ pub enum Param {
 }
 
 impl Opcode {
+    pub const ALL: [Opcode; 10] = [
+        Opcode::Add,
+        Opcode::Mult,
+        Opcode::Input,
+        Opcode::Output,
+        Opcode::JumpTrue,
+        Opcode::JumpFalse,
+        Opcode::LessThan,
+        Opcode::Equals,
+        Opcode::SetRel,
+        Opcode::Stop,
+    ];
+
+    /// Opcode for the two low digits of an instruction word.
+    #[inline]
+    pub fn from_code(code: ValueType) -> Option<Opcode> {
+        match code {
+            1 => Some(Opcode::Add),
+            2 => Some(Opcode::Mult),
+            3 => Some(Opcode::Input),
+            4 => Some(Opcode::Output),
+            5 => Some(Opcode::JumpTrue),
+            6 => Some(Opcode::JumpFalse),
+            7 => Some(Opcode::LessThan),
+            8 => Some(Opcode::Equals),
+            9 => Some(Opcode::SetRel),
+            99 => Some(Opcode::Stop),
+            _ => None,
+        }
+    }
+
+    /// Numeric opcode as found in the two low digits of an instruction word.
+    #[inline]
+    pub fn code(&self) -> ValueType {
+        match self {
+            Opcode::Add => 1,
+            Opcode::Mult => 2,
+            Opcode::Input => 3,
+            Opcode::Output => 4,
+            Opcode::JumpTrue => 5,
+            Opcode::JumpFalse => 6,
+            Opcode::LessThan => 7,
+            Opcode::Equals => 8,
+            Opcode::SetRel => 9,
+            Opcode::Stop => 99,
+            Opcode::Custom { code, .. } => *code as ValueType,
+        }
+    }
+
+    #[allow(clippy::len_without_is_empty)]
+    #[inline]
     pub fn len(&self) -> usize {
         match self {
             Opcode::Add => 4,
@@ -44,14 +146,28 @@ impl Opcode {
             Opcode::Equals => 4,
             Opcode::Stop => 0,
             Opcode::SetRel => 2,
-            Opcode::Err => 0,
+            Opcode::Custom { arity, .. } => *arity as usize + 1,
         }
     }
 
+    #[inline]
     pub fn params(&self) -> usize {
+        self.len().saturating_sub(1)
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
         match self {
-            Opcode::Err => 0,
-            x => x.len().saturating_sub(1),
+            Opcode::Add => "ADD",
+            Opcode::Mult => "MUL",
+            Opcode::Input => "IN",
+            Opcode::Output => "OUT",
+            Opcode::JumpTrue => "JT",
+            Opcode::JumpFalse => "JF",
+            Opcode::LessThan => "LT",
+            Opcode::Equals => "EQ",
+            Opcode::SetRel => "ARB",
+            Opcode::Stop => "HLT",
+            Opcode::Custom { .. } => "EXT",
         }
     }
 }
@@ -66,8 +182,20 @@ impl TryFrom<&str> for IntComputer {
     }
 }
 
+impl Param {
+    /// Mode digit used when encoding an instruction word.
+    pub fn mode(&self) -> ValueType {
+        match self {
+            Param::Pos => 0,
+            Param::Imm => 1,
+            Param::Rel => 2,
+        }
+    }
+}
+
 impl TryFrom<ValueType> for Param {
     type Error = &'static str;
+    #[inline]
     fn try_from(val: ValueType) -> Result<Self, Self::Error> {
         match val {
             0 => Ok(Param::Pos),
@@ -78,14 +206,38 @@ impl TryFrom<ValueType> for Param {
     }
 }
 
+type Queue<V = ValueType> = VecDeque<V>;
+
+/// An Intcode machine. The value type follows from the memory backend, e.g.
+/// `IntComputer<PagedMemory<i128>>` computes with 128 bit values throughout.
 #[derive(Debug, Clone)]
-pub struct IntComputer {
-    mem: Memory,
+pub struct IntComputer<
+    M = PagedMemory,
+    I = Queue<<M as Memory>::Value>,
+    O = Queue<<M as Memory>::Value>,
+> where
+    M: Memory,
+    I: InputSource<M::Value>,
+    O: OutputSink<M::Value>,
+{
+    mem: M,
     pc: usize,
     rel_base: usize,
     state: IntComputerState,
-    input: VecDeque<ValueType>,
-    output: VecDeque<ValueType>,
+    yield_on_output: bool,
+    overflow: OverflowPolicy,
+    validation: Validation,
+    limits: Limits,
+    steps: u64,
+    outputs: u64,
+    tracer: Tracer<M::Value>,
+    history: Option<History<M::Value>>,
+    coverage: Option<Coverage>,
+    profile: Option<Profile>,
+    smc: Option<SmcDetector<M::Value>>,
+    custom: CustomOps<M::Value>,
+    input: I,
+    output: O,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -93,310 +245,852 @@ pub enum IntComputerState {
     // Created,
     Initialized,
     Running,
-    Halted,
+    /// Blocked on an input instruction with an empty queue, the pc still
+    /// points at that instruction so pushing input and calling `run` resumes.
+    AwaitingInput,
+    /// An output instruction just executed while yielding on output.
+    OutputReady,
     Stopped,
 }
 
+/// A decoded instruction word, only the first `op.params()` modes are used.
+#[derive(Debug, Clone, Copy)]
 struct Instruction {
     op: Opcode,
-    params: Vec<Param>,
+    word: ValueType,
+    modes: [Param; 3],
 }
 
-const MEMSIZE: usize = 1024*1024;
-
 impl IntComputer {
     pub fn new(prog: Vec<ValueType>) -> Self {
-        // reserve MUCH more memory than needed
-        let mut mem: Memory = Vec::with_capacity(MEMSIZE);
-        for _ in 0..MEMSIZE {
-            mem.push(0);
-        }
+        IntComputer::with_memory(PagedMemory::new(prog))
+    }
+}
+
+impl<M: Memory> IntComputer<M> {
+    /// Creates a computer running on an already loaded memory backend.
+    pub fn with_memory(mem: M) -> Self {
+        IntComputer::with_io(mem, Queue::new(), Queue::new())
+    }
+}
+
+impl<M: Memory, O: OutputSink<M::Value>> IntComputer<M, Queue<M::Value>, O> {
+    pub fn push_input(&mut self, value: M::Value) {
+        self.input.push_back(value);
+    }
+}
+
+impl<M: Memory, I: InputSource<M::Value>> IntComputer<M, I, Queue<M::Value>> {
+    pub fn get_output(&mut self) -> Option<M::Value> {
+       self.output.pop_front()
+    }
 
-        zip(prog.iter(), mem.iter_mut()).for_each(|(p, m)| *m = *p as ValueType);
+    /// Runs until the next output is available and returns it, `None` means
+    /// the machine blocked on input or stopped first.
+    pub fn run_until_output(&mut self) -> Result<Option<M::Value>, IntcodeError<M::Value>> {
+        if self.output.is_empty() {
+            self.run_until(|m| !m.output.is_empty())?;
+        }
+        Ok(self.output.pop_front())
+    }
+}
 
+impl<M, I, O> IntComputer<M, I, O>
+where
+    M: Memory,
+    I: InputSource<M::Value>,
+    O: OutputSink<M::Value>,
+{
+    /// Creates a computer wired to the given input source and output sink.
+    pub fn with_io(mem: M, input: I, output: O) -> Self {
         IntComputer {
-            mem: mem,
+            mem,
             pc: 0,
             rel_base: 0,
             state: IntComputerState::Initialized,
-            input: VecDeque::new(),
-            output: VecDeque::new(),
+            yield_on_output: false,
+            overflow: OverflowPolicy::default(),
+            validation: Validation::default(),
+            limits: Limits::default(),
+            steps: 0,
+            outputs: 0,
+            tracer: Tracer::default(),
+            history: None,
+            coverage: None,
+            profile: None,
+            smc: None,
+            custom: CustomOps::default(),
+            input,
+            output,
         }
     }
 
-    pub fn get_output(&mut self) -> Option<ValueType> {
-       self.output.pop_front()
+    /// Replaces the I/O of this computer, keeping memory and registers.
+    pub fn rewire<I2, O2>(self, input: I2, output: O2) -> IntComputer<M, I2, O2>
+    where
+        I2: InputSource<M::Value>,
+        O2: OutputSink<M::Value>,
+    {
+        IntComputer {
+            mem: self.mem,
+            pc: self.pc,
+            rel_base: self.rel_base,
+            state: self.state,
+            yield_on_output: self.yield_on_output,
+            overflow: self.overflow,
+            validation: self.validation,
+            limits: self.limits,
+            steps: self.steps,
+            outputs: self.outputs,
+            tracer: self.tracer,
+            history: self.history,
+            coverage: self.coverage,
+            profile: self.profile,
+            smc: self.smc,
+            custom: self.custom,
+            input,
+            output,
+        }
+    }
+
+    pub fn input(&self) -> &I {
+        &self.input
+    }
+
+    pub fn output(&self) -> &O {
+        &self.output
+    }
+
+    pub fn input_mut(&mut self) -> &mut I {
+        &mut self.input
+    }
+
+    pub fn output_mut(&mut self) -> &mut O {
+        &mut self.output
     }
 
     pub fn get_state(&self) -> IntComputerState {
         self.state
     }
 
-    pub fn push_input(&mut self, value: ValueType) {
-        self.input.push_back(value);
+    /// When set, `run` returns `OutputReady` after every output instruction
+    /// instead of continuing until input runs dry or the program stops.
+    pub fn set_yield_on_output(&mut self, enabled: bool) {
+        self.yield_on_output = enabled;
+    }
+
+    /// Selects how `ADD` and `MUL` handle results that do not fit, the
+    /// default saturates.
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow = policy;
     }
 
-    pub fn run(&mut self) -> Result<IntComputerState, String> {
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow
+    }
+
+    /// Selects how strictly programs are checked against the specification,
+    /// the default is lenient.
+    pub fn set_validation(&mut self, validation: Validation) {
+        self.validation = validation;
+    }
+
+    pub fn validation(&self) -> Validation {
+        self.validation
+    }
+
+    pub fn memory(&self) -> &M {
+        &self.mem
+    }
+
+    pub fn memory_mut(&mut self) -> &mut M {
+        &mut self.mem
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn rel_base(&self) -> usize {
+        self.rel_base
+    }
+
+    /// Number of instructions executed so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Number of values output so far.
+    pub fn outputs(&self) -> u64 {
+        self.outputs
+    }
+
+    /// Reports every executed instruction to `sink` from now on. Clones of
+    /// this computer start out without a tracer.
+    pub fn set_tracer<T: TraceSink<M::Value> + Send + 'static>(&mut self, sink: T) {
+        self.tracer = Tracer::new(sink);
+    }
+
+    /// Detaches and returns the current trace sink.
+    pub fn take_tracer(&mut self) -> Option<Box<dyn TraceSink<M::Value> + Send>> {
+        self.tracer.take()
+    }
+
+    pub fn run(&mut self) -> Result<IntComputerState, IntcodeError<M::Value>> {
+        self.run_until(|_| false)
+    }
+
+    /// Runs until `pred` holds before the next instruction, or until the
+    /// machine blocks on input, yields an output or stops.
+    pub fn run_until<F>(&mut self, mut pred: F) -> Result<IntComputerState, IntcodeError<M::Value>>
+    where
+        F: FnMut(&Self) -> bool,
+    {
+        let start = self.profile.as_ref().map(|_| Instant::now());
+        let deadline = self.limits.max_time.map(|t| Instant::now() + t);
         loop {
-            // let x = self.step();
-            self.step()?;
+            if pred(self) {
+                break;
+            }
+            if let Err(e) = self.check_limits(deadline).and_then(|_| self.execute()) {
+                self.charge(start);
+                return Err(e);
+            }
             use IntComputerState::*;
             match self.state {
                 Initialized => (),
                 Running => (),
-                Halted | Stopped => break,
+                AwaitingInput | OutputReady | Stopped => break,
             };
         }
+        self.charge(start);
         Ok(self.state)
     }
 
-    pub fn step(&mut self) -> Result<Opcode, String> {
+    /// Executes at most `steps` instructions, the state stays `Running` if
+    /// the budget ran out first.
+    pub fn run_steps(&mut self, steps: usize) -> Result<IntComputerState, IntcodeError<M::Value>> {
+        let mut budget = steps;
+        self.run_until(|_| {
+            if budget == 0 {
+                return true;
+            }
+            budget -= 1;
+            false
+        })
+    }
+
+    /// Executes one instruction. All limits but the time limit apply, that one
+    /// only bounds the `run` calls.
+    pub fn step(&mut self) -> Result<Opcode, IntcodeError<M::Value>> {
+        self.check_limits(None)?;
+        self.execute()
+    }
+
+    fn execute(&mut self) -> Result<Opcode, IntcodeError<M::Value>> {
         let inst = self.get_instruction()?;
-        let mut iter = inst.params.iter();
+        self.check_fetch(inst.op.len())?;
+        let word = inst.word;
+        let pc = self.pc;
+        let rel_base = self.rel_base;
+        let mut iter = inst.params().iter();
+        // operand values and the store of this instruction, kept for tracing
+        let mut reads: [M::Value; 3] = Default::default();
+        let mut read_count = 0;
+        let mut write = None;
+        let prev_state = self.state;
         self.state = IntComputerState::Running;
-        let _ = match inst.op {
+        match inst.op {
             Opcode::Add => {
-                let &i1 = self.try_get_mem_ref(*iter.next().unwrap(), self.pc + 1)?;
-                let &i2 = self.try_get_mem_ref(*iter.next().unwrap(), self.pc + 2)?;
-                let out = self.try_get_mem_ref_mut(*iter.next().unwrap(), self.pc + 3)?;
-
-                let (result, overflow) = i1.overflowing_add(i2);
-                *out = i1.saturating_add(i2);
+                let i1 = self.read_param(*iter.next().unwrap(), self.pc + 1, word)?;
+                let i2 = self.read_param(*iter.next().unwrap(), self.pc + 2, word)?;
+                let out = self.resolve_write(*iter.next().unwrap(), 2, word)?;
 
-                if overflow {
-                    println!("Overflow @ {} -> {} + {}", self.pc, i1, i2);
-                }
-                // self.try_store_at(i1 + i2, out.try_into().unwrap())?;
+                let sum = i1.add_with(&i2, self.overflow);
+                let sum = sum.ok_or_else(|| self.overflow_error(word, &i1, &i2))?;
+                reads = [i1, i2, M::Value::default()];
+                read_count = 2;
+                write = Some((out, sum));
                 self.pc += inst.op.len();
-
-                Ok(true)
             }
             Opcode::Mult => {
-                let &i1 = self.try_get_mem_ref(*iter.next().unwrap(), self.pc + 1)?;
-                let &i2 = self.try_get_mem_ref(*iter.next().unwrap(), self.pc + 2)?;
-                let out = self.try_get_mem_ref_mut(*iter.next().unwrap(), self.pc + 3)?;
-                *out = i1.saturating_mul(i2);
-                // self.try_store_at(i1 * i2, out.try_into().unwrap())?;
+                let i1 = self.read_param(*iter.next().unwrap(), self.pc + 1, word)?;
+                let i2 = self.read_param(*iter.next().unwrap(), self.pc + 2, word)?;
+                let out = self.resolve_write(*iter.next().unwrap(), 2, word)?;
+                let product = i1.mul_with(&i2, self.overflow);
+                let product = product.ok_or_else(|| self.overflow_error(word, &i1, &i2))?;
+                reads = [i1, i2, M::Value::default()];
+                read_count = 2;
+                write = Some((out, product));
                 self.pc += inst.op.len();
-                Ok(true)
             }
             Opcode::Input => {
-                if self.input.is_empty() {
-                    // Err(format!("No Input supplied"))
-                    self.state = IntComputerState::Halted;
-                    Ok(false)
-                } else {
-                    let val = self.input.pop_front().unwrap();
-                    let loc = self.try_get_mem_ref_mut(*iter.next().unwrap(), self.pc + 1).unwrap();
-                    *loc = val;
-
-                    self.pc += inst.op.len();
-                    Ok(true)
+                let addr = self.resolve_write(*iter.next().unwrap(), 0, word)?;
+                match self.input.next_input() {
+                    Some(val) => {
+                        write = Some((addr, val));
+                        self.pc += inst.op.len();
+                    }
+                    None => {
+                        // nothing was executed, the instruction is retried later
+                        self.state = IntComputerState::AwaitingInput;
+                        return Ok(inst.op);
+                    }
                 }
             }
             Opcode::Output => {
-                let &out = self.try_get_mem_ref(*iter.next().unwrap(), self.pc + 1)?;
-                self.output.push_back(out);
+                let out = self.read_param(*iter.next().unwrap(), self.pc + 1, word)?;
+                if let Some(max) = self.limits.max_output.filter(|&max| self.outputs >= max) {
+                    return Err(self.limit_error(Limit::Output(max)));
+                }
+                self.output.send_output(out.clone());
+                self.outputs += 1;
+                reads[0] = out;
+                read_count = 1;
                 // println!("Output: {}", out);
                 self.pc += inst.op.len();
-                Ok(true)
+                if self.yield_on_output {
+                    self.state = IntComputerState::OutputReady;
+                }
             }
             Opcode::Stop => {
                 self.state = IntComputerState::Stopped;
-                Ok(false)
-            }
-            Opcode::JumpTrue => {
-                let &input = self.try_get_mem_ref(*iter.next().unwrap(), self.pc + 1)?;
-                if input != 0 {
-                    let &new_pc = self.try_get_mem_ref(*iter.next().unwrap(), self.pc + 2)?;
-                    let new_pc = new_pc as usize;
-                    if new_pc > self.mem.len() {
-                        return Err(format!(
-                            "New PC {} @ {} not valid, len {}",
-                            new_pc,
-                            self.pc,
-                            self.mem.len()
-                        ));
-                    }
-                    self.pc = new_pc;
-                } else {
-                    self.pc += inst.op.len();
-                }
-                Ok(true)
             }
-            Opcode::JumpFalse => {
-                let &input = self.try_get_mem_ref(*iter.next().unwrap(), self.pc + 1)?;
-                if input == 0 {
-                    let &new_pc = self.try_get_mem_ref(*iter.next().unwrap(), self.pc + 2)?;
-                    let new_pc = new_pc as usize;
-                    if new_pc > self.mem.len() {
-                        return Err(format!(
-                            "New PC {} @ {} not valid, len {}",
-                            new_pc,
-                            self.pc,
-                            self.mem.len()
-                        ));
-                    }
-                    self.pc = new_pc;
+            Opcode::JumpTrue | Opcode::JumpFalse => {
+                let input = self.read_param(*iter.next().unwrap(), self.pc + 1, word)?;
+                let jump = match inst.op {
+                    Opcode::JumpTrue => !input.is_zero(),
+                    _ => input.is_zero(),
+                };
+                reads[0] = input;
+                read_count = 1;
+                if jump {
+                    let new_pc = self.read_param(*iter.next().unwrap(), self.pc + 2, word)?;
+                    self.pc = self.jump_target(new_pc.clone(), word)?;
+                    reads[1] = new_pc;
+                    read_count = 2;
                 } else {
                     self.pc += inst.op.len();
                 }
-                Ok(true)
             }
             Opcode::LessThan => {
-                let &i1 = self.try_get_mem_ref(*iter.next().unwrap(), self.pc + 1)?;
-                let &i2 = self.try_get_mem_ref(*iter.next().unwrap(), self.pc + 2)?;
-                let out = self.try_get_mem_ref_mut(*iter.next().unwrap(), self.pc + 3)?;
+                let i1 = self.read_param(*iter.next().unwrap(), self.pc + 1, word)?;
+                let i2 = self.read_param(*iter.next().unwrap(), self.pc + 2, word)?;
+                let out = self.resolve_write(*iter.next().unwrap(), 2, word)?;
 
-                let res = if i1 < i2 { 1 } else { 0 };
-                *out = res;
+                write = Some((out, M::Value::from(if i1 < i2 { 1 } else { 0 })));
+                reads = [i1, i2, M::Value::default()];
+                read_count = 2;
                 self.pc += inst.op.len();
-                Ok(true)
             }
             Opcode::Equals => {
-                let &i1 = self.try_get_mem_ref(*iter.next().unwrap(), self.pc + 1)?;
-                let &i2 = self.try_get_mem_ref(*iter.next().unwrap(), self.pc + 2)?;
-                let out = self.try_get_mem_ref_mut(*iter.next().unwrap(), self.pc + 3)?;
+                let i1 = self.read_param(*iter.next().unwrap(), self.pc + 1, word)?;
+                let i2 = self.read_param(*iter.next().unwrap(), self.pc + 2, word)?;
+                let out = self.resolve_write(*iter.next().unwrap(), 2, word)?;
 
-                let res = if i1 == i2 { 1 } else { 0 };
-                *out = res;
+                write = Some((out, M::Value::from(if i1 == i2 { 1 } else { 0 })));
+                reads = [i1, i2, M::Value::default()];
+                read_count = 2;
                 self.pc += inst.op.len();
-                Ok(true)
             }
-            Opcode::Err => Err(format!(
-                "Invalid Opcode {} @ {}",
-                self.mem[self.pc], self.pc
-            )),
-
             Opcode::SetRel => {
-                let base = *self.try_get_mem_ref(*iter.next().unwrap(), self.pc + 1)?;
-                let rl = self.rel_base as ValueType;
-                self.rel_base = (base + rl) as usize;
+                let base = self.read_param(*iter.next().unwrap(), self.pc + 1, word)?;
+                self.rel_base = self.offset_address(self.rel_base, base.clone(), word)?;
+                reads[0] = base;
+                read_count = 1;
                 self.pc += inst.op.len();
-
-                Ok(true)
+            }
+            Opcode::Custom { code, .. } => {
+                write = self.step_custom(code, word, inst.params(), &mut reads, &mut read_count)?;
             }
         };
+        if let Some(history) = self.history.as_mut() {
+            let mem = &self.mem;
+            history.push(Delta {
+                pc,
+                rel_base,
+                state: prev_state,
+                write: write.as_ref().map(|&(addr, _)| (addr, mem.read(addr))),
+                input: if inst.op == Opcode::Input {
+                    write.as_ref().map(|(_, x)| x.clone())
+                } else {
+                    None
+                },
+                output: if inst.op == Opcode::Output {
+                    Some(reads[0].clone())
+                } else {
+                    None
+                },
+            });
+        }
+        if let Some(tracer) = self.tracer.sink() {
+            tracer.record(&TraceEvent {
+                step: self.steps,
+                pc,
+                op: inst.op,
+                word: word.into(),
+                reads: reads[..read_count].to_vec(),
+                write: write.clone(),
+                rel_base,
+            });
+        }
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record(pc, inst.op);
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.execute(pc, inst.op.len().max(1));
+            if let Some((addr, _)) = write {
+                coverage.write(addr);
+            }
+        }
+        if let Some(smc) = self.smc.as_mut() {
+            smc.execute(pc, inst.op.len().max(1));
+            if let Some((addr, value)) = &write {
+                smc.store(self.steps, pc, *addr, self.mem.read(*addr), value);
+            }
+        }
+        if let Some((addr, value)) = write {
+            self.mem.write(addr, value);
+        }
+        self.steps += 1;
         Ok(inst.op)
     }
 
-    fn try_get_mem_ref(&self, p: Param, index: usize) -> Result<&ValueType, String> {
-        if index > self.mem.len() {
-            return Err(format!(
-                "Halted @ {:04} :Index {} out of bounds",
-                self.pc, index,
-            ));
+    /// Validates a computed address, memory itself has no upper bound. A
+    /// negative address is out of bounds where it wraps to as `usize`, strict
+    /// validation names it.
+    fn check_address(
+        &self,
+        addr: M::Value,
+        word: ValueType,
+    ) -> Result<usize, IntcodeError<M::Value>> {
+        match addr.to_i64() {
+            Some(a) if a >= 0 => match self.limits.max_memory {
+                Some(max) if a as usize >= max => Err(self.limit_error(Limit::Memory(max))),
+                _ => Ok(a as usize),
+            },
+            _ if addr < M::Value::default() && self.validation == Validation::Strict => {
+                Err(IntcodeError::NegativeAddress {
+                    pc: self.pc,
+                    word: word.into(),
+                    addr,
+                })
+            }
+            Some(a) => Err(IntcodeError::OutOfBounds {
+                pc: self.pc,
+                word: word.into(),
+                addr: a as usize,
+            }),
+            _ => Err(IntcodeError::OutOfBounds {
+                pc: self.pc,
+                word: word.into(),
+                addr: usize::MAX,
+            }),
+        }
+    }
+
+    /// Validates the target of a jump.
+    fn jump_target(
+        &self,
+        target: M::Value,
+        word: ValueType,
+    ) -> Result<usize, IntcodeError<M::Value>> {
+        if self.validation == Validation::Strict && target < M::Value::default() {
+            return Err(IntcodeError::NegativeJump {
+                pc: self.pc,
+                word: word.into(),
+                target,
+            });
         }
+        self.check_address(target, word)
+    }
+
+    /// `base + offset` as an address.
+    fn offset_address(
+        &self,
+        base: usize,
+        offset: M::Value,
+        word: ValueType,
+    ) -> Result<usize, IntcodeError<M::Value>> {
+        let addr = M::Value::from(base as ValueType)
+            .add_with(&offset, OverflowPolicy::Saturating)
+            .expect("saturating addition cannot fail");
+        self.check_address(addr, word)
+    }
+
+    /// Resolves the parameter stored at `index` to the address it refers to.
+    fn resolve(
+        &self,
+        p: Param,
+        index: usize,
+        word: ValueType,
+    ) -> Result<usize, IntcodeError<M::Value>> {
         match p {
-            Param::Imm => Ok(self.mem.get(index).unwrap()),
-            Param::Pos => {
-                let location = *self.mem.get(index).unwrap() as usize;
-                match self.mem.get(location) {
-                    Some(x) => Ok(x),
-                    None => Err(format!(
-                        "Halted @ {:04} :Index {} @ {} out of bounds",
-                        self.pc, location, index
-                    )),
-                }
-            }
+            Param::Imm => Ok(index),
+            Param::Pos => self.check_address(self.mem.read(index), word),
             Param::Rel => {
                 // Relative means the value at pc+n is to be added to rel_base and this value is accessed
-                let offset = *self.mem.get(index).unwrap() as isize;
-                match self.mem.get((self.rel_base as isize + offset) as usize) {
-                    Some(x) => Ok(x),
-                    None => Err(format!(
-                        "Halted @ {:04} Offset {} base {}",
-                        self.pc, offset, self.rel_base
-                    )),
-                }
+                self.offset_address(self.rel_base, self.mem.read(index), word)
             }
         }
     }
 
-    fn try_get_mem_ref_mut(&mut self, p: Param, index: usize) -> Result<&mut ValueType, String> {
-        if index > self.mem.len() {
-            return Err(format!(
-                "Halted @ {:04} :Index {} out of bounds",
-                self.pc, index,
-            ));
+    /// Resolves write parameter `param` (0 based) of the current instruction
+    /// to the address stored at.
+    fn resolve_write(
+        &self,
+        p: Param,
+        param: usize,
+        word: ValueType,
+    ) -> Result<usize, IntcodeError<M::Value>> {
+        if p == Param::Imm && self.validation == Validation::Strict {
+            return Err(IntcodeError::ImmediateWrite {
+                pc: self.pc,
+                word: word.into(),
+                param,
+            });
         }
-        match p {
-            Param::Imm => Ok(self.mem.get_mut(index).unwrap()),
-            Param::Pos => {
-                let location = *self.mem.get(index).unwrap() as usize;
-                match self.mem.get_mut(location) {
-                    Some(x) => Ok(x),
-                    None => Err(format!(
-                        "Halted @ {:04} :Index {} @ {} out of bounds",
-                        self.pc, location, index
-                    )),
-                }
-            }
-            Param::Rel => {
-                // Relative means the value at pc+n is to be added to rel_base and this value is accessed
-                let offset = *self.mem.get(index).unwrap() as isize;
-                match self.mem.get_mut((self.rel_base as isize + offset) as usize) {
-                    Some(x) => Ok(x),
-                    None => Err(format!(
-                        "Halted @ {:04} Offset {} base {}",
-                        self.pc, offset, self.rel_base
-                    )),
-                }
+        let addr = self.resolve(p, self.pc + 1 + param, word)?;
+        if addr >= self.mem.capacity() {
+            return Err(IntcodeError::OutOfBounds {
+                pc: self.pc,
+                word: word.into(),
+                addr,
+            });
+        }
+        Ok(addr)
+    }
+
+    fn read_param(
+        &mut self,
+        p: Param,
+        index: usize,
+        word: ValueType,
+    ) -> Result<M::Value, IntcodeError<M::Value>> {
+        let addr = self.resolve(p, index, word)?;
+        if let Some(coverage) = self.coverage.as_mut() {
+            if p != Param::Imm {
+                coverage.read(addr);
             }
         }
+        Ok(self.mem.read(addr))
     }
 
-    // fn try_store_at(&mut self, value: ValueType, index: usize) -> Result<(), String> {
-    //     if index > self.mem.len() {
-    //         Err(format!(
-    //             "Halted @ {:04} :Index {} out of bounds",
-    //             self.pc, index
-    //         ))
-    //     } else {
-    //         self.mem[index] = value;
-    //         Ok(())
-    //     }
-    // }
-
-    fn get_instruction(&self) -> Result<Instruction, String> {
-        if self.pc > self.mem.len() {
-            return Err(format!(
-                "PC out of bounds: {:04} length {}",
-                self.pc,
-                self.mem.len()
-            ));
+    fn overflow_error(
+        &self,
+        word: ValueType,
+        lhs: &M::Value,
+        rhs: &M::Value,
+    ) -> IntcodeError<M::Value> {
+        IntcodeError::Overflow {
+            pc: self.pc,
+            word: word.into(),
+            lhs: lhs.clone(),
+            rhs: rhs.clone(),
         }
-        let x = *self.mem.get(self.pc).unwrap() as ValueType;
-        let op = if x > 99 { x % 100 } else { x };
-        let mut params = x / 100;
+    }
+
+    fn get_instruction(&self) -> Result<Instruction, IntcodeError<M::Value>> {
+        let word = self.mem.read(self.pc);
+        let word = match word.to_i64() {
+            Some(word) => word,
+            None => return Err(IntcodeError::InvalidOpcode { pc: self.pc, word }),
+        };
+        let op = self.custom.opcode(word % 100);
+        Instruction::decode_as(op.or_else(|| Opcode::from_code(word % 100)), word, self.pc)
+    }
+}
+
+impl Instruction {
+    /// Builds the instruction word for `op` with the given parameter modes.
+    pub(crate) fn encode(op: Opcode, modes: &[Param]) -> ValueType {
+        modes
+            .iter()
+            .rev()
+            .fold(0, |word, p| word * 10 + p.mode())
+            * 100
+            + op.code()
+    }
+
+    /// Splits an instruction word into opcode and parameter modes without
+    /// allocating, `pc` is only used for error reporting.
+    #[inline]
+    fn decode<V: Value>(word: ValueType, pc: usize) -> Result<Instruction, IntcodeError<V>> {
+        Instruction::decode_as(Opcode::from_code(word % 100), word, pc)
+    }
+
+    /// Like `decode` with the opcode already looked up.
+    #[inline]
+    fn decode_as<V: Value>(
+        op: Option<Opcode>,
+        word: ValueType,
+        pc: usize,
+    ) -> Result<Instruction, IntcodeError<V>> {
+        let mut params = word / 100;
 
         let op = match op {
-            1 => Opcode::Add,
-            2 => Opcode::Mult,
-            3 => Opcode::Input,
-            4 => Opcode::Output,
-            5 => Opcode::JumpTrue,
-            6 => Opcode::JumpFalse,
-            7 => Opcode::LessThan,
-            8 => Opcode::Equals,
-            9 => Opcode::SetRel,
-            99 => Opcode::Stop,
-            _ => Opcode::Err,
+            Some(op) => op,
+            None => {
+                return Err(IntcodeError::InvalidOpcode {
+                    pc,
+                    word: word.into(),
+                })
+            }
         };
-        let mut result: Vec<Param> = Vec::new();
-        for _ in 0..op.params() {
-            let p = params % 10;
-            let p: Param = match Param::try_from(p) {
-                Ok(x) => x,
-                Err(_) => return Err(format!("Invalid parameter @ pc {}", self.pc)),
-            };
-            result.push(p);
+        let mut modes = [Param::Pos; 3];
+        for (param, slot) in modes.iter_mut().take(op.params()).enumerate() {
+            let mode = params % 10;
+            *slot = Param::try_from(mode).map_err(|_| IntcodeError::InvalidParamMode {
+                pc,
+                word: word.into(),
+                param,
+                mode,
+            })?;
             params /= 10;
         }
 
-        Ok(Instruction {
-            op: op,
-            params: result,
-        })
+        Ok(Instruction { op, word, modes })
+    }
+
+    /// Modes of the parameters the opcode actually takes.
+    #[inline]
+    fn params(&self) -> &[Param] {
+        &self.modes[..self.op.params()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invalid_opcode() {
+        let mut program = IntComputer::try_from("1,0,0,0,42").unwrap();
+
+        let err = program.run().unwrap_err();
+        assert_eq!(err, IntcodeError::InvalidOpcode { pc: 4, word: 42 });
+    }
+
+    #[test]
+    fn test_invalid_param_mode() {
+        let mut program = IntComputer::try_from("3001,0,0,0,99").unwrap();
+
+        let err = program.run().unwrap_err();
+        assert_eq!(
+            err,
+            IntcodeError::InvalidParamMode {
+                pc: 0,
+                word: 3001,
+                param: 1,
+                mode: 3
+            }
+        );
+    }
+
+    #[test]
+    fn test_negative_address() {
+        let mut program = IntComputer::try_from("109,5,204,-10,99").unwrap();
+        program.set_validation(Validation::Strict);
+
+        let err = program.run().unwrap_err();
+        assert_eq!(
+            err,
+            IntcodeError::NegativeAddress {
+                pc: 2,
+                word: 204,
+                addr: -5
+            }
+        );
+        assert_eq!(err.pc(), 2);
+        assert_eq!(err.word(), Some(204));
+    }
+
+    #[test]
+    fn test_validation() {
+        let run = |program: &str, validation| {
+            let mut program = IntComputer::try_from(program).unwrap();
+            program.set_validation(validation);
+            program.push_input(7);
+            program.run().map(|_| program.memory().read(1))
+        };
+        // IN #0 stores over its own parameter
+        let imm_write = "103,0,99";
+        assert_eq!(run(imm_write, Validation::Lenient), Ok(7));
+        assert_eq!(
+            run(imm_write, Validation::Strict),
+            Err(IntcodeError::ImmediateWrite {
+                pc: 0,
+                word: 103,
+                param: 0
+            })
+        );
+        assert_eq!(
+            run("11101,1,2,3,99", Validation::Strict).unwrap_err(),
+            IntcodeError::ImmediateWrite {
+                pc: 0,
+                word: 11101,
+                param: 2
+            }
+        );
+
+        let jump = "1105,1,-4,99";
+        assert_eq!(
+            run(jump, Validation::Lenient),
+            Err(IntcodeError::OutOfBounds {
+                pc: 0,
+                word: 1105,
+                addr: -4i64 as usize
+            })
+        );
+        assert_eq!(
+            run(jump, Validation::Strict),
+            Err(IntcodeError::NegativeJump {
+                pc: 0,
+                word: 1105,
+                target: -4
+            })
+        );
+        assert_eq!(
+            run("4,-1,99", Validation::Lenient),
+            Err(IntcodeError::OutOfBounds {
+                pc: 0,
+                word: 4,
+                addr: usize::MAX
+            })
+        );
+        assert!(matches!(
+            run("4,-1,99", Validation::Strict),
+            Err(IntcodeError::NegativeAddress { pc: 0, addr: -1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_overflow_policy() {
+        let run = |program: &str, policy| {
+            let mut program = IntComputer::try_from(program).unwrap();
+            program.set_overflow_policy(policy);
+            program.run().map(|_| program.get_output().unwrap())
+        };
+        let add = "1101,9223372036854775807,2,9,4,9,99";
+        let mul = "1102,-4611686018427387905,2,9,4,9,99";
+
+        assert_eq!(run(add, OverflowPolicy::Saturating), Ok(ValueType::MAX));
+        assert_eq!(run(add, OverflowPolicy::Wrapping), Ok(ValueType::MIN + 1));
+        assert_eq!(run(mul, OverflowPolicy::Saturating), Ok(ValueType::MIN));
+        assert_eq!(run(mul, OverflowPolicy::Wrapping), Ok(ValueType::MAX - 1));
+        assert_eq!(
+            run(mul, OverflowPolicy::Checked),
+            Err(IntcodeError::Overflow {
+                pc: 0,
+                word: 1102,
+                lhs: -4611686018427387905,
+                rhs: 2
+            })
+        );
+
+        let mut program = IntComputer::try_from(add).unwrap();
+        program.set_overflow_policy(OverflowPolicy::Checked);
+        assert!(program.run().is_err());
+        assert_eq!(program.pc(), 0);
+        assert_eq!(program.memory().read(9), 0);
+    }
+
+    #[test]
+    fn test_address_beyond_program() {
+        let mut program = IntComputer::try_from("1101,7,8,2000000,4,2000000,99").unwrap();
+
+        program.run().unwrap();
+        assert_eq!(program.get_output(), Some(15));
+        assert_eq!(program.memory().read(2000000), 15);
+    }
+
+    #[test]
+    fn test_vec_memory() {
+        let mut program = IntComputer::with_memory(vec![1101 as ValueType, 7, 8, 20, 4, 20, 99]);
+
+        program.run().unwrap();
+        assert_eq!(program.get_output(), Some(15));
+        assert_eq!(program.memory().len(), 21);
+    }
+
+    #[test]
+    fn test_awaiting_input() {
+        let mut program = IntComputer::try_from("3,9,4,9,3,9,4,9,99,0").unwrap();
+
+        assert_eq!(program.run(), Ok(IntComputerState::AwaitingInput));
+        assert_eq!(program.run(), Ok(IntComputerState::AwaitingInput));
+        program.push_input(5);
+        assert_eq!(program.run(), Ok(IntComputerState::AwaitingInput));
+        assert_eq!(program.get_output(), Some(5));
+        program.push_input(6);
+        assert_eq!(program.run(), Ok(IntComputerState::Stopped));
+        assert_eq!(program.get_output(), Some(6));
+    }
+
+    #[test]
+    fn test_output_ready() {
+        let mut program = IntComputer::try_from("104,1,104,2,99").unwrap();
+        program.set_yield_on_output(true);
+
+        assert_eq!(program.run(), Ok(IntComputerState::OutputReady));
+        assert_eq!(program.get_output(), Some(1));
+        assert_eq!(program.run(), Ok(IntComputerState::OutputReady));
+        assert_eq!(program.get_output(), Some(2));
+        assert_eq!(program.run(), Ok(IntComputerState::Stopped));
+        assert_eq!(program.get_output(), None);
+    }
+
+    #[test]
+    fn test_run_until_output() {
+        let mut program = IntComputer::try_from("104,1,104,2,3,0,99").unwrap();
+
+        assert_eq!(program.run_until_output(), Ok(Some(1)));
+        assert_eq!(program.pc(), 2);
+        assert_eq!(program.run_until_output(), Ok(Some(2)));
+        assert_eq!(program.run_until_output(), Ok(None));
+        assert_eq!(program.get_state(), IntComputerState::AwaitingInput);
+    }
+
+    #[test]
+    fn test_run_steps() {
+        // loops forever
+        let mut program = IntComputer::try_from("1105,1,0").unwrap();
+
+        assert_eq!(program.run_steps(1000), Ok(IntComputerState::Running));
+        assert_eq!(program.pc(), 0);
+    }
+
+    #[test]
+    fn test_run_until() {
+        let mut program = IntComputer::try_from("109,10,109,-3,109,5,99").unwrap();
+
+        let state = program.run_until(|m| m.rel_base() == 7);
+        assert_eq!(state, Ok(IntComputerState::Running));
+        assert_eq!(program.pc(), 4);
+        assert_eq!(program.run(), Ok(IntComputerState::Stopped));
+        assert_eq!(program.rel_base(), 12);
+    }
+
+    #[test]
+    fn test_wired_computers() {
+        let link = SharedQueue::default();
+        let double: Vec<ValueType> = parse_program("3,11,102,2,11,11,4,11,1105,1,0,0").unwrap();
+        let add: Vec<ValueType> = parse_program("3,11,101,1,11,11,4,11,1105,1,0,0").unwrap();
+        let mut first = IntComputer::with_io(
+            PagedMemory::new(double),
+            IterInput(vec![1, 2, 3].into_iter()),
+            link.clone(),
+        );
+        let mut outputs = Vec::new();
+        let mut second = IntComputer::with_io(
+            PagedMemory::new(add),
+            link,
+            FnOutput(|x| outputs.push(x)),
+        );
+
+        assert_eq!(first.run(), Ok(IntComputerState::AwaitingInput));
+        assert_eq!(second.run(), Ok(IntComputerState::AwaitingInput));
+        drop(second);
+        assert_eq!(outputs, vec![3, 5, 7]);
     }
 }
@@ -0,0 +1,245 @@
+//! Assembler for the text syntax produced by the disassembler.
+//!
+//! ```text
+//! ; comments run to the end of the line
+//! start:  IN   [value]            ; position mode
+//!         MUL  [value], #3, rb+1  ; immediate and relative mode
+//!         JT   #1, #start         ; labels resolve to addresses
+//!         HLT
+//! value:  DATA 0, -1
+//! ```
+//!
+//! Mnemonics and directives are case insensitive, a leading address column
+//! as printed in listings is skipped.
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use super::{Instruction, Opcode, Param, ValueType};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AsmErrorKind {
+    UnknownMnemonic(String),
+    BadOperand(String),
+    OperandCount { expected: usize, found: usize },
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+}
+
+/// Error with the 1 based source line it occurred on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub kind: AsmErrorKind,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use AsmErrorKind::*;
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            UnknownMnemonic(m) => write!(f, "unknown mnemonic {}", m),
+            BadOperand(o) => write!(f, "malformed operand {}", o),
+            OperandCount { expected, found } => {
+                write!(f, "expected {} operands, found {}", expected, found)
+            }
+            DuplicateLabel(l) => write!(f, "label {} defined twice", l),
+            UndefinedLabel(l) => write!(f, "label {} is not defined", l),
+        }
+    }
+}
+
+impl Error for AsmError {}
+
+/// Number or label, labels are resolved once all of them are known.
+enum Value<'a> {
+    Literal(ValueType),
+    Label(&'a str),
+}
+
+enum Word<'a> {
+    Op(ValueType),
+    Arg(Value<'a>),
+}
+
+fn is_label(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+fn parse_value(s: &str) -> Option<Value<'_>> {
+    let s = s.trim();
+    if is_label(s) {
+        Some(Value::Label(s))
+    } else {
+        s.parse().ok().map(Value::Literal)
+    }
+}
+
+fn parse_operand(s: &str) -> Option<(Param, Value<'_>)> {
+    let s = s.trim();
+    if let Some(rest) = s.strip_prefix('#') {
+        parse_value(rest).map(|v| (Param::Imm, v))
+    } else if let Some(rest) = s.strip_prefix('[').and_then(|r| r.strip_suffix(']')) {
+        parse_value(rest).map(|v| (Param::Pos, v))
+    } else if let Some(rest) = s.strip_prefix("rb") {
+        let rest = rest.trim_start();
+        match rest.strip_prefix('+') {
+            _ if rest.is_empty() => Some((Param::Rel, Value::Literal(0))),
+            Some(offset) => parse_value(offset).map(|v| (Param::Rel, v)),
+            None if rest.starts_with('-') => parse_value(rest).map(|v| (Param::Rel, v)),
+            None => None,
+        }
+    } else {
+        None
+    }
+}
+
+fn split_args(s: &str) -> Vec<&str> {
+    if s.trim().is_empty() {
+        Vec::new()
+    } else {
+        s.split(',').map(str::trim).collect()
+    }
+}
+
+/// Translates assembler source into a program for `IntComputer::new`.
+pub fn assemble(source: &str) -> Result<Vec<ValueType>, AsmError> {
+    let mut words: Vec<(usize, Word)> = Vec::new();
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+
+    for (n, line) in source.lines().enumerate() {
+        let n = n + 1;
+        let err = |kind| AsmError { line: n, kind };
+        let mut rest = line.split(';').next().unwrap().trim();
+
+        // address column of a listing
+        if rest.starts_with(|c: char| c.is_ascii_digit()) {
+            rest = rest.trim_start_matches(|c: char| c.is_ascii_digit()).trim_start();
+        }
+        if let Some(colon) = rest.find(':') {
+            let label = rest[..colon].trim();
+            if is_label(label) {
+                if labels.insert(label, words.len()).is_some() {
+                    return Err(err(AsmErrorKind::DuplicateLabel(label.to_string())));
+                }
+                rest = rest[colon + 1..].trim();
+            }
+        }
+        if rest.is_empty() {
+            continue;
+        }
+
+        let (mnemonic, args) = match rest.find(char::is_whitespace) {
+            Some(i) => (&rest[..i], split_args(&rest[i..])),
+            None => (rest, Vec::new()),
+        };
+
+        if mnemonic.eq_ignore_ascii_case("data") {
+            for arg in args {
+                let value = parse_value(arg)
+                    .ok_or_else(|| err(AsmErrorKind::BadOperand(arg.to_string())))?;
+                words.push((n, Word::Arg(value)));
+            }
+            continue;
+        }
+
+        let op = Opcode::ALL
+            .iter()
+            .find(|op| op.mnemonic().eq_ignore_ascii_case(mnemonic))
+            .ok_or_else(|| err(AsmErrorKind::UnknownMnemonic(mnemonic.to_string())))?;
+        if args.len() != op.params() {
+            return Err(err(AsmErrorKind::OperandCount {
+                expected: op.params(),
+                found: args.len(),
+            }));
+        }
+        let operands = args
+            .iter()
+            .map(|arg| {
+                parse_operand(arg).ok_or_else(|| err(AsmErrorKind::BadOperand(arg.to_string())))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let modes = operands.iter().map(|&(p, _)| p).collect::<Vec<_>>();
+        words.push((n, Word::Op(Instruction::encode(*op, &modes))));
+        words.extend(operands.into_iter().map(|(_, v)| (n, Word::Arg(v))));
+    }
+
+    words
+        .into_iter()
+        .map(|(n, word)| match word {
+            Word::Op(x) | Word::Arg(Value::Literal(x)) => Ok(x),
+            Word::Arg(Value::Label(l)) => match labels.get(l) {
+                Some(&addr) => Ok(addr as ValueType),
+                None => Err(AsmError {
+                    line: n,
+                    kind: AsmErrorKind::UndefinedLabel(l.to_string()),
+                }),
+            },
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int_code::{disassemble, parse_program, IntComputer};
+    use std::fs;
+
+    #[test]
+    fn test_assemble_program() {
+        let source = "
+            ; count down from the input
+            start:  IN   [n]
+            loop:   OUT  [n]
+                    ADD  [n], #-1, [n]
+                    JT   [n], #loop
+                    HLT
+            n:      DATA 0
+        ";
+        let program = assemble(source).unwrap();
+        assert_eq!(program, parse_program("3,12,4,12,1001,12,-1,12,1005,12,2,99,0").unwrap());
+
+        let mut computer = IntComputer::new(program);
+        computer.push_input(3);
+        computer.run().unwrap();
+        let mut result = Vec::new();
+        while let Some(x) = computer.get_output() {
+            result.push(x);
+        }
+        assert_eq!(result, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_assemble_relative() {
+        let program = assemble("ARB #5\nOUT rb-2\nIN rb\nIN rb+7\nDATA start\nstart: HLT").unwrap();
+        assert_eq!(program, vec![109, 5, 204, -2, 203, 0, 203, 7, 9, 99]);
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let err = assemble("HLT\nADD #1, #2").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert_eq!(err.kind, AsmErrorKind::OperandCount { expected: 3, found: 2 });
+
+        let err = assemble("JT #1, #nowhere").unwrap_err();
+        assert_eq!(err.kind, AsmErrorKind::UndefinedLabel("nowhere".to_string()));
+
+        let err = assemble("OUT 5").unwrap_err();
+        assert_eq!(err.kind, AsmErrorKind::BadOperand("5".to_string()));
+    }
+
+    #[test]
+    fn test_disassembly_round_trip() {
+        let input = fs::read_to_string("puzzle_input.txt").unwrap();
+        let program = parse_program(&input).unwrap();
+
+        let listing = disassemble(&program).to_string();
+        assert_eq!(assemble(&listing).unwrap(), program);
+    }
+}
@@ -0,0 +1,283 @@
+//! Control flow graph of a program, recovered without running it.
+//!
+//! Decoding starts at address 0 and follows every path the decoder can
+//! predict. Basic blocks end at jumps, `HLT` and words that do not decode;
+//! jumps whose target is not an immediate operand get an `Unknown` edge since
+//! the target is only known at run time. Self-modifying code is not accounted
+//! for, the graph describes the program as loaded.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use super::disasm::{decode_line, Item, Line, Operand};
+use super::{Opcode, Param, ValueType};
+
+/// Where control goes when leaving a block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Addr(usize),
+    /// Computed at run time, e.g. a jump through a position operand.
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Execution continues with the next instruction.
+    Fallthrough,
+    /// A jump is taken.
+    Jump,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    pub kind: EdgeKind,
+    pub to: Target,
+}
+
+/// A straight run of instructions only entered at the top.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    pub lines: Vec<Line>,
+    pub exits: Vec<Edge>,
+}
+
+impl Block {
+    /// Address following the last instruction of the block.
+    pub fn end(&self) -> usize {
+        self.lines
+            .last()
+            .map_or(self.start, |l| l.addr + l.words.len())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    /// Reachable blocks by start address.
+    pub blocks: BTreeMap<usize, Block>,
+}
+
+/// Whether `line` ends a block and where execution can go from it.
+fn exits(line: &Line) -> (bool, Vec<Edge>) {
+    let next = Edge {
+        kind: EdgeKind::Fallthrough,
+        to: Target::Addr(line.addr + line.words.len()),
+    };
+    let (op, operands) = match &line.item {
+        Item::Code { op, operands } => (*op, operands),
+        Item::Data(_) => return (true, Vec::new()),
+    };
+    match op {
+        Opcode::Stop => (true, Vec::new()),
+        Opcode::JumpTrue | Opcode::JumpFalse => {
+            let jump = Edge {
+                kind: EdgeKind::Jump,
+                to: line.jump_target().map_or(Target::Unknown, Target::Addr),
+            };
+            // an immediate condition makes the jump unconditional or a no-op
+            let taken = match operands[0] {
+                Operand {
+                    mode: Param::Imm,
+                    value,
+                } => Some((value != 0) == (op == Opcode::JumpTrue)),
+                _ => None,
+            };
+            match taken {
+                Some(true) => (true, vec![jump]),
+                Some(false) => (true, vec![next]),
+                None => (true, vec![jump, next]),
+            }
+        }
+        _ => (false, vec![next]),
+    }
+}
+
+/// Recovers the control flow graph of `program` starting at address 0.
+pub fn control_flow(program: &[ValueType]) -> Cfg {
+    let mut lines = BTreeMap::new();
+    let mut leaders = BTreeSet::new();
+    leaders.insert(0);
+    let mut work = vec![0];
+    while let Some(addr) = work.pop() {
+        if addr >= program.len() || lines.contains_key(&addr) {
+            continue;
+        }
+        let line = decode_line(addr, &program[addr..]);
+        let (ends, edges) = exits(&line);
+        for edge in edges {
+            if let Target::Addr(to) = edge.to {
+                if ends {
+                    leaders.insert(to);
+                }
+                work.push(to);
+            }
+        }
+        lines.insert(addr, line);
+    }
+
+    let mut blocks = BTreeMap::new();
+    for &start in leaders.iter().filter(|a| lines.contains_key(a)) {
+        let mut block = Block {
+            start,
+            lines: Vec::new(),
+            exits: Vec::new(),
+        };
+        let mut addr = start;
+        loop {
+            let line = &lines[&addr];
+            let (ends, edges) = exits(line);
+            block.lines.push(line.clone());
+            addr += line.words.len();
+            if ends || leaders.contains(&addr) || !lines.contains_key(&addr) {
+                block.exits = edges;
+                break;
+            }
+        }
+        blocks.insert(start, block);
+    }
+    Cfg { blocks }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl Cfg {
+    /// The graph in Graphviz DOT format, one box per block listing its
+    /// instructions. Unknown jump targets point at a `?` node of their own,
+    /// targets past the end of the program at an `oNNNN` node naming the
+    /// address.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph cfg {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for block in self.blocks.values() {
+            let text: String = block
+                .lines
+                .iter()
+                .map(|l| format!("{}\\l", escape(&l.to_string())))
+                .collect();
+            writeln!(dot, "    b{:04} [label=\"{}\"];", block.start, text).unwrap();
+        }
+        for block in self.blocks.values() {
+            for edge in &block.exits {
+                let style = match edge.kind {
+                    EdgeKind::Fallthrough => "",
+                    EdgeKind::Jump => " [label=\"jump\"]",
+                };
+                match edge.to {
+                    Target::Addr(to) if !self.blocks.contains_key(&to) => {
+                        writeln!(
+                            dot,
+                            "    o{:04} [label=\"{:04}\\nout of program\", shape=none];",
+                            to, to
+                        )
+                        .unwrap();
+                        writeln!(dot, "    b{:04} -> o{:04}{};", block.start, to, style).unwrap();
+                    }
+                    Target::Addr(to) => {
+                        writeln!(dot, "    b{:04} -> b{:04}{};", block.start, to, style).unwrap()
+                    }
+                    Target::Unknown => {
+                        writeln!(dot, "    u{:04} [label=\"?\", shape=none];", block.start)
+                            .unwrap();
+                        writeln!(
+                            dot,
+                            "    b{:04} -> u{:04} [label=\"jump\", style=dashed];",
+                            block.start, block.start
+                        )
+                        .unwrap();
+                    }
+                }
+            }
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int_code::parse_program;
+
+    fn jump(to: usize) -> Edge {
+        Edge {
+            kind: EdgeKind::Jump,
+            to: Target::Addr(to),
+        }
+    }
+
+    fn next(to: usize) -> Edge {
+        Edge {
+            kind: EdgeKind::Fallthrough,
+            to: Target::Addr(to),
+        }
+    }
+
+    #[test]
+    fn test_blocks() {
+        // 0: IN [13]; JF [13], #9; OUT #1; HLT; 9: OUT #0; JT #1, #7
+        let program = parse_program("3,13,1006,13,9,104,1,99,99,104,0,1105,1,7").unwrap();
+        let cfg = control_flow(&program);
+
+        let starts: Vec<usize> = cfg.blocks.keys().copied().collect();
+        assert_eq!(starts, vec![0, 5, 7, 9]);
+        assert_eq!(cfg.blocks[&0].lines.len(), 2);
+        assert_eq!(cfg.blocks[&0].exits, vec![jump(9), next(5)]);
+        assert_eq!(cfg.blocks[&5].exits, vec![next(7)]);
+        assert_eq!(cfg.blocks[&7].exits, vec![]);
+        assert_eq!(cfg.blocks[&9].exits, vec![jump(7)]);
+        assert_eq!(cfg.blocks[&9].end(), 14);
+        // the second HLT at 8 is never reached
+        assert!(cfg
+            .blocks
+            .values()
+            .all(|b| b.lines.iter().all(|l| l.addr != 8)));
+    }
+
+    #[test]
+    fn test_indirect_jump() {
+        // 0: ADD #0, #7, [20]; JT #1, #10; OUT #1; HLT; 10: OUT #0; JT #1, [20]
+        let program = parse_program("1101,0,7,20,1105,1,10,104,1,99,104,0,105,1,20").unwrap();
+        let cfg = control_flow(&program);
+
+        assert_eq!(cfg.blocks[&0].exits, vec![jump(10)]);
+        assert_eq!(
+            cfg.blocks[&10].exits,
+            vec![Edge {
+                kind: EdgeKind::Jump,
+                to: Target::Unknown
+            }]
+        );
+        // the return address is only known at run time
+        assert!(!cfg.blocks.contains_key(&7));
+    }
+
+    #[test]
+    fn test_dot() {
+        let program = parse_program("3,10,1005,10,7,99,99,6,10,99,0").unwrap();
+        let dot = control_flow(&program).to_dot();
+
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("    b0000 -> b0007 [label=\"jump\"];\n"));
+        assert!(dot.contains("    b0000 -> b0005;\n"));
+        assert!(dot.contains("    b0007 -> u0007 [label=\"jump\", style=dashed];\n"));
+        assert!(dot.contains("    b0007 -> b0010;\n"));
+        assert!(dot.contains("    b0010 [label=\"0010  DATA 0\\l\"];\n"));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn test_dot_out_of_program() {
+        // jumps past the end of the program
+        let program = parse_program("1105,1,20,99").unwrap();
+        let cfg = control_flow(&program);
+        assert_eq!(cfg.blocks[&0].exits, vec![jump(20)]);
+        let dot = cfg.to_dot();
+
+        assert!(dot.contains("    o0020 [label=\"0020\\nout of program\", shape=none];\n"));
+        assert!(dot.contains("    b0000 -> o0020 [label=\"jump\"];\n"));
+        assert!(!dot.contains("b0020"));
+    }
+}
@@ -0,0 +1,227 @@
+//! Which addresses a run used, and how.
+//!
+//! Executed instruction words and their parameter words are the program,
+//! addresses only read or written through operands are its data. Words of
+//! the loaded program that show up in neither were never reached.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::{InputSource, IntComputer, Memory, OutputSink};
+
+/// How often one address was used.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    /// Executed as an instruction word.
+    pub executed: u64,
+    /// Fetched as a parameter word of an executed instruction.
+    pub operand: u64,
+    /// Read through a position or relative mode operand.
+    pub read: u64,
+    /// Stored to.
+    pub written: u64,
+}
+
+impl Usage {
+    pub fn is_code(&self) -> bool {
+        self.executed > 0 || self.operand > 0
+    }
+
+    /// One character summary used by `Coverage::heatmap`.
+    pub fn symbol(&self) -> char {
+        match *self {
+            u if u.is_code() && u.written > 0 => '!',
+            u if u.executed > 0 => '#',
+            u if u.operand > 0 => '+',
+            u if u.read > 0 && u.written > 0 => '*',
+            u if u.written > 0 => 'w',
+            u if u.read > 0 => 'r',
+            _ => '.',
+        }
+    }
+}
+
+/// Usage of every address touched since coverage was enabled.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    usage: BTreeMap<usize, Usage>,
+}
+
+impl Coverage {
+    pub fn get(&self, addr: usize) -> Usage {
+        self.usage.get(&addr).copied().unwrap_or_default()
+    }
+
+    /// Touched addresses in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Usage)> {
+        self.usage.iter().map(|(&addr, usage)| (addr, usage))
+    }
+
+    /// Records an executed instruction of `len` words at `pc`.
+    pub(super) fn execute(&mut self, pc: usize, len: usize) {
+        self.usage.entry(pc).or_default().executed += 1;
+        for addr in pc + 1..pc + len {
+            self.usage.entry(addr).or_default().operand += 1;
+        }
+    }
+
+    pub(super) fn read(&mut self, addr: usize) {
+        self.usage.entry(addr).or_default().read += 1;
+    }
+
+    pub(super) fn write(&mut self, addr: usize) {
+        self.usage.entry(addr).or_default().written += 1;
+    }
+
+    /// One character per address from 0 up to `len` or the highest touched
+    /// address, `width` addresses per line:
+    ///
+    /// `#` instruction, `+` parameter, `r` read, `w` written, `*` read and
+    /// written, `!` code that was written to, `.` untouched.
+    ///
+    /// Past `len` only lines with a touched address are drawn, the lines
+    /// between them collapse into one line counting the skipped addresses.
+    pub fn heatmap(&self, len: usize, width: usize) -> String {
+        let width = width.max(1);
+        let end = self
+            .usage
+            .keys()
+            .next_back()
+            .map_or(len, |&a| len.max(a + 1));
+        let mut rows: Vec<usize> = (0..len).step_by(width).collect();
+        for &addr in self.usage.range(len..).map(|(a, _)| a) {
+            let row = addr - addr % width;
+            if rows.last() != Some(&row) {
+                rows.push(row);
+            }
+        }
+        let mut map = String::new();
+        let mut next = 0;
+        for row in rows {
+            if row > next {
+                map.push_str(&format!("....  {} untouched\n", row - next));
+            }
+            next = end.min(row + width);
+            let cells: String = (row..next).map(|addr| self.get(addr).symbol()).collect();
+            map.push_str(&format!("{:04}  {}\n", row, cells));
+        }
+        map
+    }
+}
+
+/// Table of the touched addresses with their counts.
+impl fmt::Display for Coverage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "addr     executed    operand       read    written")?;
+        for (addr, u) in self.iter() {
+            writeln!(
+                f,
+                "{:04}  {:>11} {:>10} {:>10} {:>10}",
+                addr, u.executed, u.operand, u.read, u.written
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<M, I, O> IntComputer<M, I, O>
+where
+    M: Memory,
+    I: InputSource<M::Value>,
+    O: OutputSink<M::Value>,
+{
+    /// Starts collecting coverage, discarding what was collected before.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::default());
+    }
+
+    /// Stops collecting coverage and returns what was collected.
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int_code::parse_program;
+
+    #[test]
+    fn test_coverage() {
+        // adds the input to [21] as often as [22] says, 16 to 19 are padding
+        let program =
+            parse_program("3,20,1,20,21,21,101,-1,22,22,1005,22,2,4,21,99,0,0,0,0,0,0,3").unwrap();
+        let mut computer = IntComputer::new(program);
+        computer.enable_coverage();
+        computer.push_input(5);
+        computer.run().unwrap();
+        assert_eq!(computer.get_output(), Some(15));
+        let coverage = computer.coverage().unwrap();
+
+        assert_eq!(coverage.get(0).executed, 1);
+        assert_eq!(coverage.get(1).operand, 1);
+        assert_eq!(coverage.get(2).executed, 3);
+        assert_eq!(coverage.get(12).operand, 3);
+        assert!(coverage.get(15).is_code());
+        assert_eq!(coverage.get(16), Usage::default());
+        assert_eq!(
+            coverage.get(20),
+            Usage {
+                read: 3,
+                written: 1,
+                ..Usage::default()
+            }
+        );
+        assert_eq!(
+            coverage.get(21),
+            Usage {
+                read: 4,
+                written: 3,
+                ..Usage::default()
+            }
+        );
+        assert_eq!(
+            coverage.get(22),
+            Usage {
+                read: 6,
+                written: 3,
+                ..Usage::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_reports() {
+        // stores over its own first instruction
+        let program = parse_program("1101,1,1,0,4,0,99").unwrap();
+        let mut computer = IntComputer::new(program);
+        computer.enable_coverage();
+        computer.run().unwrap();
+        let coverage = computer.take_coverage().unwrap();
+
+        assert_eq!(coverage.heatmap(8, 4), "0000  !+++\n0004  #+#.\n");
+        let table = coverage.to_string();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(
+            lines[1],
+            "0000            1          0          1          1"
+        );
+        assert!(computer.coverage().is_none());
+
+        // one store far past the program
+        let program = parse_program("21101,1,1,1000000000,99").unwrap();
+        let mut computer = IntComputer::new(program);
+        computer.enable_coverage();
+        computer.run().unwrap();
+        let coverage = computer.take_coverage().unwrap();
+        assert_eq!(
+            coverage.heatmap(5, 4),
+            "0000  #+++\n0004  #...\n....  999999992 untouched\n1000000000  w\n"
+        );
+    }
+}
@@ -0,0 +1,318 @@
+//! User defined instructions.
+//!
+//! A `CustomOp` gives an opcode number an arity, says which of its parameters
+//! are stored to and supplies a handler for the rest. Registered on a machine
+//! it takes precedence over the built-in instruction with the same number, so
+//! dialects can redefine existing opcodes as well as add new ones. Handlers
+//! see the operand values and decide what is stored, where execution
+//! continues and whether the machine stops.
+
+use std::fmt;
+use std::sync::Arc;
+
+use super::{
+    InputSource, IntComputer, IntComputerState, IntcodeError, Memory, Opcode, OutputSink,
+    ValueType,
+};
+
+/// How an instruction uses one of its parameters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// The operand is read, in any mode.
+    Read,
+    /// The operand is the address the result is stored at.
+    Write,
+}
+
+/// One execution of a custom instruction, as seen by its handler.
+#[derive(Debug)]
+pub struct CustomCall<'a, V> {
+    /// Address of the instruction.
+    pub pc: usize,
+    /// Values of the read parameters, in parameter order.
+    pub args: &'a [V],
+    /// Value stored through the write parameter, nothing is stored if unset.
+    pub result: Option<V>,
+    /// Continue at this address instead of the next instruction.
+    pub jump: Option<V>,
+    /// Stop the machine after this instruction.
+    pub halt: bool,
+}
+
+/// Address and value an instruction stores.
+type Store<V> = Option<(usize, V)>;
+
+type Handler<V> = dyn Fn(&mut CustomCall<V>) -> Result<(), String> + Send + Sync;
+
+/// An instruction defined outside of the interpreter.
+pub struct CustomOp<V = ValueType> {
+    code: u8,
+    params: Vec<Access>,
+    handler: Arc<Handler<V>>,
+}
+
+impl<V> CustomOp<V> {
+    /// Instruction `code` (1 to 99) taking `params` in order. At most three
+    /// parameters and at most one write are supported, like the built-in
+    /// instructions. An error returned by `handler` stops the machine with
+    /// `IntcodeError::Handler`.
+    pub fn new<F>(code: u8, params: &[Access], handler: F) -> Result<Self, &'static str>
+    where
+        F: Fn(&mut CustomCall<V>) -> Result<(), String> + Send + Sync + 'static,
+    {
+        if code == 0 || code > 99 {
+            return Err("opcode must be between 1 and 99");
+        }
+        if params.len() > 3 {
+            return Err("at most three parameters are supported");
+        }
+        if params.iter().filter(|&&p| p == Access::Write).count() > 1 {
+            return Err("at most one parameter can be written");
+        }
+        Ok(CustomOp {
+            code,
+            params: params.to_vec(),
+            handler: Arc::new(handler),
+        })
+    }
+
+    pub fn code(&self) -> u8 {
+        self.code
+    }
+
+    pub fn params(&self) -> &[Access] {
+        &self.params
+    }
+
+    fn opcode(&self) -> Opcode {
+        Opcode::Custom {
+            code: self.code,
+            arity: self.params.len() as u8,
+        }
+    }
+}
+
+impl<V> Clone for CustomOp<V> {
+    fn clone(&self) -> Self {
+        CustomOp {
+            code: self.code,
+            params: self.params.clone(),
+            handler: Arc::clone(&self.handler),
+        }
+    }
+}
+
+impl<V> fmt::Debug for CustomOp<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CustomOp")
+            .field("code", &self.code)
+            .field("params", &self.params)
+            .finish()
+    }
+}
+
+/// Custom instructions of a machine, indexed by opcode. Clones share the
+/// handlers.
+#[derive(Debug)]
+pub(super) struct CustomOps<V>(Vec<Option<CustomOp<V>>>);
+
+impl<V> CustomOps<V> {
+    /// The instruction registered for `code`, if any.
+    #[inline]
+    pub(super) fn opcode(&self, code: ValueType) -> Option<Opcode> {
+        if self.0.is_empty() || !(0..100).contains(&code) {
+            return None;
+        }
+        self.0[code as usize].as_ref().map(CustomOp::opcode)
+    }
+
+    fn get(&self, code: u8) -> &CustomOp<V> {
+        self.0[code as usize].as_ref().expect("decoded custom opcode")
+    }
+}
+
+impl<V> Default for CustomOps<V> {
+    fn default() -> Self {
+        CustomOps(Vec::new())
+    }
+}
+
+impl<V> Clone for CustomOps<V> {
+    fn clone(&self) -> Self {
+        CustomOps(self.0.clone())
+    }
+}
+
+impl<M, I, O> IntComputer<M, I, O>
+where
+    M: Memory,
+    I: InputSource<M::Value>,
+    O: OutputSink<M::Value>,
+{
+    /// Adds `op` to the instruction set of this machine, replacing whatever
+    /// instruction had the same opcode before.
+    pub fn register_op(&mut self, op: CustomOp<M::Value>) {
+        let table = &mut self.custom.0;
+        if table.is_empty() {
+            table.resize_with(100, || None);
+        }
+        let code = op.code as usize;
+        table[code] = Some(op);
+    }
+
+    /// Removes the custom instruction `code`, a replaced built-in instruction
+    /// comes back.
+    pub fn unregister_op(&mut self, code: u8) -> Option<CustomOp<M::Value>> {
+        self.custom.0.get_mut(code as usize)?.take()
+    }
+
+    /// Executes the custom instruction `code` at pc, filling in the operand
+    /// values read and returning the store to perform.
+    pub(super) fn step_custom(
+        &mut self,
+        code: u8,
+        word: ValueType,
+        modes: &[super::Param],
+        reads: &mut [M::Value; 3],
+        read_count: &mut usize,
+    ) -> Result<Store<M::Value>, IntcodeError<M::Value>> {
+        let op = self.custom.get(code).clone();
+        let mut target = None;
+        for (i, (&mode, &access)) in modes.iter().zip(&op.params).enumerate() {
+            match access {
+                Access::Read => {
+                    reads[*read_count] = self.read_param(mode, self.pc + 1 + i, word)?;
+                    *read_count += 1;
+                }
+                Access::Write => target = Some(self.resolve_write(mode, i, word)?),
+            }
+        }
+
+        let mut call = CustomCall {
+            pc: self.pc,
+            args: &reads[..*read_count],
+            result: None,
+            jump: None,
+            halt: false,
+        };
+        (op.handler)(&mut call).map_err(|message| IntcodeError::Handler {
+            pc: self.pc,
+            word: word.into(),
+            message,
+        })?;
+
+        let CustomCall {
+            result, jump, halt, ..
+        } = call;
+        if halt {
+            self.state = IntComputerState::Stopped;
+        } else if let Some(target) = jump {
+            self.pc = self.jump_target(target, word)?;
+        } else {
+            self.pc += op.params.len() + 1;
+        }
+        Ok(target.zip(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int_code::assemble;
+    use crate::int_code::trace::TraceRing;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_invalid_definitions() {
+        let nop = |_: &mut CustomCall<ValueType>| Ok(());
+        assert!(CustomOp::new(0, &[], nop).is_err());
+        assert!(CustomOp::new(100, &[], nop).is_err());
+        assert!(CustomOp::new(50, &[Access::Read; 4], nop).is_err());
+        assert!(CustomOp::new(50, &[Access::Write, Access::Write], nop).is_err());
+    }
+
+    #[test]
+    fn test_override_builtin() {
+        // a dialect where MUL raises the first operand to the second
+        let params = [Access::Read, Access::Read, Access::Write];
+        let pow = CustomOp::new(2, &params, |call: &mut CustomCall<ValueType>| {
+            call.result = Some(call.args[0].pow(call.args[1] as u32));
+            Ok(())
+        })
+        .unwrap();
+        let program = assemble("MUL #3, #4, [7]\nOUT [7]\nHLT").unwrap();
+        let mut computer = IntComputer::new(program.clone());
+        computer.register_op(pow.clone());
+        let ring = TraceRing::new(10);
+        computer.set_tracer(ring.clone());
+        computer.run().unwrap();
+
+        assert_eq!(computer.get_output(), Some(81));
+        assert_eq!(ring.events()[0].op, Opcode::Custom { code: 2, arity: 3 });
+        assert_eq!(ring.events()[0].write, Some((7, 81)));
+
+        let mut computer = IntComputer::new(program);
+        computer.register_op(pow);
+        assert!(computer.unregister_op(2).is_some());
+        computer.run().unwrap();
+        assert_eq!(computer.get_output(), Some(12));
+    }
+
+    #[test]
+    fn test_instrumentation() {
+        // 50: count executions, 51 a: stop unless a is zero
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&hits);
+        let count = CustomOp::new(50, &[], move |_| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        })
+        .unwrap();
+        let assert_zero = CustomOp::new(51, &[Access::Read], |call| match call.args[0] {
+            0 => Ok(()),
+            x => Err(format!("expected 0, found {}", x)),
+        })
+        .unwrap();
+
+        // three times round a loop counting [11] down from 3, then check it
+        let program = vec![50, 1001, 11, -1, 11, 1005, 11, 0, 51, 11, 99, 3];
+        let mut computer = IntComputer::new(program);
+        computer.register_op(count);
+        computer.register_op(assert_zero.clone());
+        assert_eq!(computer.run(), Ok(IntComputerState::Stopped));
+        assert_eq!(hits.load(Ordering::Relaxed), 3);
+
+        let mut computer = IntComputer::new(vec![151, 5, 99]);
+        computer.register_op(assert_zero);
+        assert_eq!(
+            computer.run(),
+            Err(IntcodeError::Handler {
+                pc: 0,
+                word: 151,
+                message: "expected 0, found 5".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_jump_and_halt() {
+        // 60 a: jump to a, 61: halt
+        let jump = CustomOp::new(60, &[Access::Read], |call| {
+            call.jump = Some(call.args[0]);
+            Ok(())
+        })
+        .unwrap();
+        let halt = CustomOp::new(61, &[], |call| {
+            call.halt = true;
+            Ok(())
+        })
+        .unwrap();
+        let mut computer = IntComputer::new(vec![160, 4, 104, 1, 61, 104, 2, 99]);
+        computer.register_op(jump);
+        computer.register_op(halt);
+
+        assert_eq!(computer.run(), Ok(IntComputerState::Stopped));
+        assert_eq!(computer.pc(), 4);
+        assert_eq!(computer.get_output(), None);
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use super::disasm::{decode_line, Line};
+use super::{
+    IntComputer, IntComputerState, IntcodeError, Memory, PagedMemory, SmcEvent, ValueType,
+};
+
+const HELP: &str = "\
+s, step [N]         execute N instructions (default 1)
+bs, back [N]        undo N instructions (default 1)
+g, goto STEP        move to the point after STEP instructions, either way
+c, continue         run until a breakpoint, watchpoint, input wait or stop
+b, break ADDR       stop before executing the instruction at ADDR
+w, watch ADDR       stop when the value at ADDR changes
+d, delete ADDR      remove breakpoint and watchpoint at ADDR
+i, info             list breakpoints and watchpoints
+smc [0|1]           list stores into code, 1 stops on them, 0 stops detecting
+r, regs             show pc, relative base, state and I/O queues
+x ADDR [N]          print N memory cells starting at ADDR, at most 256
+who ADDR            step of the last recorded write to ADDR
+p, poke ADDR VALUE  store VALUE at ADDR
+l, list [N]         disassemble N instructions from pc
+in VALUE...         queue input values
+out                 print and drain the output queue
+q, quit             leave the debugger";
+
+/// Why `Debugger::cont` handed control back.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    Breakpoint(usize),
+    Watchpoint {
+        addr: usize,
+        old: ValueType,
+        new: ValueType,
+    },
+    /// An instruction stored into code, see `Debugger::break_on_smc`.
+    SelfModify(SmcEvent),
+    /// The machine blocked on input, yielded an output or stopped.
+    State(IntComputerState),
+}
+
+/// Instructions kept for stepping backwards.
+const HISTORY: usize = 1 << 20;
+
+/// Memory cells `x` prints at most.
+const MAX_CELLS: usize = 256;
+
+/// Interactive front end around `IntComputer::step`.
+pub struct Debugger<M: Memory = PagedMemory> {
+    machine: IntComputer<M>,
+    breakpoints: BTreeSet<usize>,
+    watches: BTreeMap<usize, ValueType>,
+    break_on_smc: bool,
+}
+
+impl<M: Memory<Value = ValueType>> Debugger<M> {
+    /// Takes over `machine` and starts recording its history.
+    pub fn new(mut machine: IntComputer<M>) -> Self {
+        machine.enable_history(HISTORY);
+        Debugger {
+            machine,
+            breakpoints: BTreeSet::new(),
+            watches: BTreeMap::new(),
+            break_on_smc: false,
+        }
+    }
+
+    pub fn machine(&self) -> &IntComputer<M> {
+        &self.machine
+    }
+
+    pub fn machine_mut(&mut self) -> &mut IntComputer<M> {
+        &mut self.machine
+    }
+
+    pub fn add_breakpoint(&mut self, addr: usize) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn add_watch(&mut self, addr: usize) {
+        let value = self.machine.memory().read(addr);
+        self.watches.insert(addr, value);
+    }
+
+    /// Makes `cont` stop after an instruction that stores into code. Turning
+    /// it off also stops detection.
+    pub fn break_on_smc(&mut self, enabled: bool) {
+        if enabled && !self.break_on_smc {
+            self.machine.enable_smc_detection();
+        } else if !enabled {
+            self.machine.disable_smc_detection();
+        }
+        self.break_on_smc = enabled;
+    }
+
+    /// Removes the breakpoint and watchpoint at `addr`.
+    pub fn delete(&mut self, addr: usize) -> bool {
+        let b = self.breakpoints.remove(&addr);
+        let w = self.watches.remove(&addr).is_some();
+        b || w
+    }
+
+    /// Decodes `count` instructions starting at `addr` straight from memory.
+    pub fn listing(&self, addr: usize, count: usize) -> Vec<Line> {
+        let mut addr = addr;
+        (0..count)
+            .map(|_| {
+                let words: Vec<ValueType> =
+                    (addr..addr + 4).map(|a| self.machine.memory().read(a)).collect();
+                let line = decode_line(addr, &words);
+                addr += line.words.len();
+                line
+            })
+            .collect()
+    }
+
+    /// The instruction the machine is about to execute.
+    pub fn current(&self) -> Line {
+        self.listing(self.machine.pc(), 1).remove(0)
+    }
+
+    pub fn step(&mut self, steps: usize) -> Result<IntComputerState, IntcodeError> {
+        let state = self.machine.run_steps(steps);
+        self.sync_watches();
+        state
+    }
+
+    /// Undoes up to `steps` instructions, returns how many were undone.
+    pub fn back(&mut self, steps: usize) -> usize {
+        let undone = (0..steps).take_while(|_| self.machine.step_back()).count();
+        self.sync_watches();
+        undone
+    }
+
+    /// Runs until a breakpoint or watchpoint triggers or the machine cannot
+    /// continue on its own. A breakpoint at the current pc is stepped over.
+    pub fn cont(&mut self) -> Result<StopReason, IntcodeError> {
+        let breakpoints = &self.breakpoints;
+        let watches = &self.watches;
+        let break_on_smc = self.break_on_smc;
+        let seen = self.machine.smc_events().len();
+        let mut first = true;
+        let mut reason = None;
+        let state = self.machine.run_until(|m| {
+            if first {
+                first = false;
+                return false;
+            }
+            if let Some((&addr, &old)) = watches.iter().find(|(&a, &v)| m.memory().read(a) != v) {
+                let new = m.memory().read(addr);
+                reason = Some(StopReason::Watchpoint { addr, old, new });
+            } else if break_on_smc && m.smc_events().len() > seen {
+                reason = Some(StopReason::SelfModify(m.smc_events()[seen].clone()));
+            } else if breakpoints.contains(&m.pc()) {
+                reason = Some(StopReason::Breakpoint(m.pc()));
+            }
+            reason.is_some()
+        });
+        let reason = reason.or_else(|| self.changed_watch()).or_else(|| {
+            let event = self.machine.smc_events().get(seen).filter(|_| break_on_smc);
+            event.cloned().map(StopReason::SelfModify)
+        });
+        self.sync_watches();
+        Ok(reason.unwrap_or(StopReason::State(state?)))
+    }
+
+    fn changed_watch(&self) -> Option<StopReason> {
+        self.watches.iter().find_map(|(&addr, &old)| {
+            let new = self.machine.memory().read(addr);
+            if new != old {
+                Some(StopReason::Watchpoint { addr, old, new })
+            } else {
+                None
+            }
+        })
+    }
+
+    fn sync_watches(&mut self) {
+        for (&addr, value) in self.watches.iter_mut() {
+            *value = self.machine.memory().read(addr);
+        }
+    }
+
+    /// Executes one command line and returns the text to show, `None` once
+    /// the user asked to quit.
+    pub fn execute(&mut self, command: &str) -> Option<String> {
+        let mut words = command.split_whitespace();
+        let cmd = match words.next() {
+            Some(cmd) => cmd,
+            None => return Some(String::new()),
+        };
+        let args: Result<Vec<ValueType>, _> = words.map(str::parse).collect();
+        let args = match args {
+            Ok(args) => args,
+            Err(_) => return Some(format!("invalid argument in '{}'", command.trim())),
+        };
+        let addr = |i: usize| args.get(i).filter(|&&a| a >= 0).map(|&a| a as usize);
+
+        let mut out = String::new();
+        match (cmd, addr(0)) {
+            ("q", _) | ("quit", _) => return None,
+            ("h", _) | ("help", _) => out.push_str(HELP),
+            ("s", _) | ("step", _) => {
+                let steps = addr(0).unwrap_or(1);
+                match self.step(steps) {
+                    Ok(state) => write!(out, "{:?}\n{}", state, self.current()).unwrap(),
+                    Err(e) => write!(out, "error: {}", e).unwrap(),
+                }
+            }
+            ("bs", _) | ("back", _) => {
+                let undone = self.back(addr(0).unwrap_or(1));
+                write!(
+                    out,
+                    "undid {}, step {}\n{}",
+                    undone,
+                    self.machine.steps(),
+                    self.current()
+                )
+                .unwrap();
+            }
+            ("g", Some(a)) | ("goto", Some(a)) => {
+                if let Err(e) = self.machine.goto_step(a as u64) {
+                    write!(out, "cannot reach step {}: {}, ", a, e).unwrap();
+                }
+                self.sync_watches();
+                write!(out, "step {}\n{}", self.machine.steps(), self.current()).unwrap();
+            }
+            ("who", Some(a)) => match self.machine.last_write(a) {
+                Some(step) => write!(out, "{:04} last written by step {}", a, step).unwrap(),
+                None => write!(out, "no recorded write to {:04}", a).unwrap(),
+            },
+            ("c", _) | ("continue", _) => match self.cont() {
+                Ok(StopReason::Watchpoint { addr, old, new }) => write!(
+                    out,
+                    "watch {}: {} -> {}\n{}",
+                    addr,
+                    old,
+                    new,
+                    self.current()
+                )
+                .unwrap(),
+                Ok(StopReason::SelfModify(event)) => {
+                    write!(out, "{}\n{}", event, self.current()).unwrap()
+                }
+                Ok(reason) => write!(out, "{:?}\n{}", reason, self.current()).unwrap(),
+                Err(e) => write!(out, "error: {}", e).unwrap(),
+            },
+            ("b", Some(a)) | ("break", Some(a)) => {
+                self.add_breakpoint(a);
+                write!(out, "breakpoint at {:04}", a).unwrap();
+            }
+            ("w", Some(a)) | ("watch", Some(a)) => {
+                self.add_watch(a);
+                write!(out, "watching {:04}", a).unwrap();
+            }
+            ("d", Some(a)) | ("delete", Some(a)) => {
+                if !self.delete(a) {
+                    write!(out, "nothing set at {:04}", a).unwrap();
+                }
+            }
+            ("i", _) | ("info", _) => write!(
+                out,
+                "breakpoints {:?}\nwatchpoints {:?}",
+                self.breakpoints, self.watches
+            )
+            .unwrap(),
+            ("smc", Some(on)) => {
+                self.break_on_smc(on != 0);
+                let state = if on != 0 { "stopping" } else { "not detecting" };
+                write!(out, "{} on stores into code", state).unwrap();
+            }
+            ("smc", None) => {
+                let events: Vec<String> = self
+                    .machine
+                    .smc_events()
+                    .iter()
+                    .map(SmcEvent::to_string)
+                    .collect();
+                out.push_str(&events.join("\n"));
+            }
+            ("r", _) | ("regs", _) => write!(
+                out,
+                "pc {:04} rb {} state {:?}\ninput {:?}\noutput {:?}\n{}",
+                self.machine.pc(),
+                self.machine.rel_base(),
+                self.machine.get_state(),
+                self.machine.input(),
+                self.machine.output(),
+                self.current()
+            )
+            .unwrap(),
+            ("x", Some(a)) => {
+                let count = addr(1).unwrap_or(1).min(MAX_CELLS);
+                let cells: Vec<String> = (a..a.saturating_add(count))
+                    .map(|x| self.machine.memory().read(x).to_string())
+                    .collect();
+                write!(out, "{:04}: {}", a, cells.join(" ")).unwrap();
+            }
+            ("p", Some(a)) | ("poke", Some(a)) if args.len() == 2 => {
+                let capacity = self.machine.memory().capacity();
+                if a < capacity {
+                    self.machine.memory_mut().write(a, args[1]);
+                    self.sync_watches();
+                } else {
+                    write!(out, "cannot store at {:04}, memory ends at {}", a, capacity).unwrap();
+                }
+            }
+            ("l", _) | ("list", _) => {
+                let lines = self.listing(self.machine.pc(), addr(0).unwrap_or(10));
+                let lines: Vec<String> = lines.iter().map(Line::to_string).collect();
+                out.push_str(&lines.join("\n"));
+            }
+            ("in", _) => args.iter().for_each(|&x| self.machine.push_input(x)),
+            ("out", _) => {
+                let values: Vec<String> = std::iter::from_fn(|| self.machine.get_output())
+                    .map(|x| x.to_string())
+                    .collect();
+                out.push_str(&values.join(" "));
+            }
+            _ => write!(out, "unknown command '{}', try help", command.trim()).unwrap(),
+        }
+        Some(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int_code::assemble;
+
+    fn counter() -> Debugger {
+        let program = assemble(
+            "
+            loop: ADD  [n], #1, [n]
+                  EQ   [n], #3, [done]
+                  JF   [done], #loop
+                  OUT  [n]
+                  HLT
+            n:    DATA 0
+            done: DATA 0
+            ",
+        )
+        .unwrap();
+        Debugger::new(IntComputer::new(program))
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut debugger = counter();
+        debugger.add_breakpoint(0);
+
+        assert_eq!(debugger.cont(), Ok(StopReason::Breakpoint(0)));
+        assert_eq!(debugger.machine().memory().read(14), 1);
+        assert_eq!(debugger.cont(), Ok(StopReason::Breakpoint(0)));
+        assert_eq!(debugger.machine().memory().read(14), 2);
+        assert!(debugger.delete(0));
+        assert_eq!(debugger.cont(), Ok(StopReason::State(IntComputerState::Stopped)));
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let mut debugger = counter();
+        debugger.add_watch(15);
+
+        let reason = debugger.cont();
+        assert_eq!(
+            reason,
+            Ok(StopReason::Watchpoint {
+                addr: 15,
+                old: 0,
+                new: 1
+            })
+        );
+        assert_eq!(debugger.machine().pc(), 8);
+    }
+
+    #[test]
+    fn test_break_on_smc() {
+        // OUT #1 patched to OUT #2 by the ADD, in an endless loop
+        let program = vec![104, 1, 1101, 0, 2, 1, 1105, 1, 0];
+        let mut debugger = Debugger::new(IntComputer::new(program));
+        let text = debugger.execute("smc 1").unwrap();
+        assert_eq!(text, "stopping on stores into code");
+
+        match debugger.cont() {
+            Ok(StopReason::SelfModify(event)) => assert_eq!((event.pc, event.target), (2, 1)),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(debugger.machine().pc(), 6);
+        let text = debugger.execute("smc").unwrap();
+        assert_eq!(text, "step 1 @ 0002 rewrites executed code at 0001: 1 -> 2");
+        debugger.execute("smc 0").unwrap();
+        assert!(debugger.machine().smc_events().is_empty());
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut debugger = counter();
+
+        assert_eq!(debugger.execute("p 14 2").unwrap(), "");
+        assert_eq!(debugger.execute("x 14 2").unwrap(), "0014: 2 0");
+        let text = debugger.execute("s").unwrap();
+        assert!(text.ends_with("EQ   [14], #3, [15]              ; 1008,14,3,15"));
+        debugger.execute("c").unwrap();
+        assert_eq!(debugger.execute("who 14").unwrap(), "0014 last written by step 0");
+        let text = debugger.execute("bs 2").unwrap();
+        assert!(text.starts_with("undid 2, step 3\n0011  OUT"));
+        let text = debugger.execute("g 0").unwrap();
+        assert!(text.starts_with("step 0\n0000  ADD"));
+        assert_eq!(debugger.execute("x 14").unwrap(), "0014: 2");
+
+        // an output taken from the queue cannot be undone
+        debugger.execute("c").unwrap();
+        assert_eq!(debugger.execute("out").unwrap(), "3");
+        let text = debugger.execute("bs 2").unwrap();
+        assert!(text.starts_with("undid 1, step 4\n0013  HLT"));
+        let text = debugger.execute("g 0").unwrap();
+        assert!(text.starts_with("cannot reach step 0: output of step 3 was already taken, step 4"));
+        assert_eq!(debugger.execute("q"), None);
+    }
+
+    #[test]
+    fn test_bounds() {
+        let mut debugger = Debugger::new(IntComputer::with_memory(vec![99]));
+
+        let text = debugger.execute("p 99999999 1").unwrap();
+        assert_eq!(text, "cannot store at 99999999, memory ends at 16777216");
+        assert_eq!(debugger.machine().memory().len(), 1);
+        let text = debugger.execute("x 0 99999999").unwrap();
+        assert_eq!(text.split(' ').count(), 1 + MAX_CELLS);
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::{Instruction, Opcode, Param, ValueType};
+
+/// A decoded parameter, shown as `[x]` (position), `#x` (immediate) or
+/// `rb+x` (relative).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Operand {
+    pub mode: Param,
+    pub value: ValueType,
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mode {
+            Param::Pos => write!(f, "[{}]", self.value),
+            Param::Imm => write!(f, "#{}", self.value),
+            Param::Rel if self.value < 0 => write!(f, "rb{}", self.value),
+            Param::Rel => write!(f, "rb+{}", self.value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Code { op: Opcode, operands: Vec<Operand> },
+    /// A word that does not decode to a complete instruction.
+    Data(ValueType),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub addr: usize,
+    pub words: Vec<ValueType>,
+    pub item: Item,
+}
+
+impl Line {
+    /// Address a jump on this line goes to, if it is known statically.
+    pub fn jump_target(&self) -> Option<usize> {
+        match &self.item {
+            Item::Code {
+                op: Opcode::JumpTrue,
+                operands,
+            }
+            | Item::Code {
+                op: Opcode::JumpFalse,
+                operands,
+            } => match operands[1] {
+                Operand {
+                    mode: Param::Imm,
+                    value,
+                } if value >= 0 => Some(value as usize),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// Result of a linear sweep over a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Listing {
+    pub lines: Vec<Line>,
+    pub labels: BTreeMap<usize, String>,
+}
+
+impl Listing {
+    pub fn label(&self, addr: usize) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+}
+
+/// Decodes the instruction at `addr`, `words` starts with the instruction word
+/// and holds whatever follows it.
+pub fn decode_line(addr: usize, words: &[ValueType]) -> Line {
+    let word = words[0];
+    match Instruction::decode::<ValueType>(word, addr) {
+        // words carrying stray mode digits stay data so listings reassemble exactly
+        Ok(inst)
+            if inst.params().len() < words.len()
+                && Instruction::encode(inst.op, inst.params()) == word =>
+        {
+            let operands = inst
+                .params()
+                .iter()
+                .zip(&words[1..])
+                .map(|(&mode, &value)| Operand { mode, value })
+                .collect::<Vec<_>>();
+            Line {
+                addr,
+                words: words[..=operands.len()].to_vec(),
+                item: Item::Code {
+                    op: inst.op,
+                    operands,
+                },
+            }
+        }
+        _ => Line {
+            addr,
+            words: vec![word],
+            item: Item::Data(word),
+        },
+    }
+}
+
+/// Decodes `program` from address 0 on. Jump targets given as immediates get
+/// a label when they line up with the start of a decoded line.
+pub fn disassemble(program: &[ValueType]) -> Listing {
+    let mut lines = Vec::new();
+    let mut addr = 0;
+    while addr < program.len() {
+        let line = decode_line(addr, &program[addr..]);
+        addr += line.words.len();
+        lines.push(line);
+    }
+
+    let starts = lines.iter().map(|l| l.addr).collect::<Vec<_>>();
+    let labels = lines
+        .iter()
+        .filter_map(Line::jump_target)
+        .filter(|target| starts.binary_search(target).is_ok())
+        .map(|target| (target, format!("L{:04}", target)))
+        .collect();
+
+    Listing { lines, labels }
+}
+
+impl Line {
+    fn write(&self, f: &mut fmt::Formatter, target: Option<&str>) -> fmt::Result {
+        match &self.item {
+            Item::Code { op, operands } => {
+                let mut args = operands.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+                if let Some(label) = target {
+                    args[1] = format!("#{}", label);
+                }
+                let raw = self.words.iter().map(|w| w.to_string()).collect::<Vec<_>>();
+                let text = format!("{:<4} {}", op.mnemonic(), args.join(", "));
+                write!(f, "{:04}  {:<32} ; {}", self.addr, text.trim_end(), raw.join(","))
+            }
+            Item::Data(value) => write!(f, "{:04}  DATA {}", self.addr, value),
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, None)
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in self.lines.iter() {
+            if let Some(label) = self.label(line.addr) {
+                writeln!(f, "{}:", label)?;
+            }
+            line.write(f, line.jump_target().and_then(|t| self.label(t)))?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int_code::parse_program;
+
+    #[test]
+    fn test_disassemble_modes() {
+        let program = parse_program("1002,4,3,4,33,204,-1,21101,1,2,3").unwrap();
+        let listing = disassemble(&program);
+
+        let text = listing.to_string();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "0000  MUL  [4], #3, [4]                ; 1002,4,3,4");
+        assert_eq!(lines[1], "0004  DATA 33");
+        assert_eq!(lines[2], "0005  OUT  rb-1                        ; 204,-1");
+        assert_eq!(lines[3], "0007  ADD  #1, #2, rb+3                ; 21101,1,2,3");
+    }
+
+    #[test]
+    fn test_disassemble_labels() {
+        let program = parse_program("3,9,1005,9,7,99,99,104,1,99").unwrap();
+        let listing = disassemble(&program);
+
+        assert_eq!(listing.label(7), Some("L0007"));
+        assert_eq!(listing.labels.len(), 1);
+        assert!(listing.to_string().contains("JT   [9], #L0007"));
+        assert!(listing.to_string().contains("L0007:\n0007  OUT"));
+    }
+
+    #[test]
+    fn test_disassemble_truncated() {
+        let listing = disassemble(&[1101, 1]);
+
+        assert_eq!(listing.lines.len(), 2);
+        assert_eq!(listing.lines[0].item, Item::Data(1101));
+        assert_eq!(disassemble(&[10099]).lines[0].item, Item::Data(10099));
+    }
+}
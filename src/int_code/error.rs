@@ -0,0 +1,174 @@
+use std::error::Error;
+use std::fmt;
+
+use super::{Limit, Value, ValueType};
+
+/// Everything that can go wrong while decoding or executing an instruction.
+///
+/// Each variant carries the program counter and, where one was fetched, the raw
+/// instruction word so failures can be matched on and reported without parsing
+/// strings. `V` is the value type of the machine that failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntcodeError<V = ValueType> {
+    /// The two low digits of the instruction word are not a known opcode.
+    InvalidOpcode { pc: usize, word: V },
+    /// Parameter `param` (0 based) uses a mode digit other than 0, 1 or 2.
+    InvalidParamMode {
+        pc: usize,
+        word: V,
+        param: usize,
+        mode: ValueType,
+    },
+    /// An operand resolved to an address outside of the machine's memory,
+    /// addresses beyond `usize` are reported as `usize::MAX`.
+    OutOfBounds { pc: usize, word: V, addr: usize },
+    /// An operand resolved to an address below zero.
+    NegativeAddress { pc: usize, word: V, addr: V },
+    /// A jump instruction targeted an address below zero.
+    NegativeJump { pc: usize, word: V, target: V },
+    /// A write parameter was given in immediate mode.
+    ImmediateWrite { pc: usize, word: V, param: usize },
+    /// An arithmetic instruction produced a result the overflow policy rejected.
+    Overflow { pc: usize, word: V, lhs: V, rhs: V },
+    /// The handler of a custom instruction reported a failure.
+    Handler { pc: usize, word: V, message: String },
+    /// A resource limit of the machine was reached before the instruction at
+    /// `pc`, after `steps` instructions.
+    LimitExceeded { pc: usize, steps: u64, limit: Limit },
+}
+
+impl<V: Value> IntcodeError<V> {
+    /// Program counter of the instruction that failed.
+    pub fn pc(&self) -> usize {
+        use IntcodeError::*;
+        match *self {
+            InvalidOpcode { pc, .. }
+            | InvalidParamMode { pc, .. }
+            | OutOfBounds { pc, .. }
+            | NegativeAddress { pc, .. }
+            | NegativeJump { pc, .. }
+            | ImmediateWrite { pc, .. }
+            | Overflow { pc, .. }
+            | Handler { pc, .. }
+            | LimitExceeded { pc, .. } => pc,
+        }
+    }
+
+    /// Raw instruction word at `pc`, if it could be fetched.
+    pub fn word(&self) -> Option<V> {
+        use IntcodeError::*;
+        match self {
+            InvalidOpcode { word, .. }
+            | InvalidParamMode { word, .. }
+            | OutOfBounds { word, .. }
+            | NegativeAddress { word, .. }
+            | NegativeJump { word, .. }
+            | ImmediateWrite { word, .. }
+            | Overflow { word, .. }
+            | Handler { word, .. } => Some(word.clone()),
+            LimitExceeded { .. } => None,
+        }
+    }
+}
+
+impl<V> IntcodeError<V> {
+    /// The same error with its values converted, e.g. to a wider type.
+    pub fn convert<W: From<V>>(self) -> IntcodeError<W> {
+        use IntcodeError::*;
+        match self {
+            InvalidOpcode { pc, word } => InvalidOpcode {
+                pc,
+                word: word.into(),
+            },
+            InvalidParamMode {
+                pc,
+                word,
+                param,
+                mode,
+            } => InvalidParamMode {
+                pc,
+                word: word.into(),
+                param,
+                mode,
+            },
+            OutOfBounds { pc, word, addr } => OutOfBounds {
+                pc,
+                word: word.into(),
+                addr,
+            },
+            NegativeAddress { pc, word, addr } => NegativeAddress {
+                pc,
+                word: word.into(),
+                addr: addr.into(),
+            },
+            NegativeJump { pc, word, target } => NegativeJump {
+                pc,
+                word: word.into(),
+                target: target.into(),
+            },
+            ImmediateWrite { pc, word, param } => ImmediateWrite {
+                pc,
+                word: word.into(),
+                param,
+            },
+            Overflow { pc, word, lhs, rhs } => Overflow {
+                pc,
+                word: word.into(),
+                lhs: lhs.into(),
+                rhs: rhs.into(),
+            },
+            Handler { pc, word, message } => Handler {
+                pc,
+                word: word.into(),
+                message,
+            },
+            LimitExceeded { pc, steps, limit } => LimitExceeded { pc, steps, limit },
+        }
+    }
+}
+
+impl<V: Value> fmt::Display for IntcodeError<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use IntcodeError::*;
+        match self {
+            InvalidOpcode { pc, word } => write!(f, "invalid opcode {} @ {:04}", word, pc),
+            InvalidParamMode {
+                pc,
+                word,
+                param,
+                mode,
+            } => write!(
+                f,
+                "invalid mode {} for parameter {} of {} @ {:04}",
+                mode, param, word, pc
+            ),
+            OutOfBounds { pc, word, addr } => write!(
+                f,
+                "address {} out of bounds in {} @ {:04}",
+                addr, word, pc
+            ),
+            NegativeAddress { pc, word, addr } => {
+                write!(f, "negative address {} in {} @ {:04}", addr, word, pc)
+            }
+            NegativeJump { pc, word, target } => {
+                write!(f, "negative jump target {} in {} @ {:04}", target, word, pc)
+            }
+            ImmediateWrite { pc, word, param } => write!(
+                f,
+                "parameter {} of {} @ {:04} writes in immediate mode",
+                param, word, pc
+            ),
+            Overflow { pc, word, lhs, rhs } => write!(
+                f,
+                "overflow in {} @ {:04} with operands {} and {}",
+                word, pc, lhs, rhs
+            ),
+            Handler { pc, word, message } => write!(f, "{} @ {:04} failed: {}", word, pc, message),
+            LimitExceeded { pc, steps, limit } => {
+                write!(f, "{} exceeded @ {:04} after {} steps", limit, pc, steps)
+            }
+        }
+    }
+}
+
+impl<V: Value> Error for IntcodeError<V> {}
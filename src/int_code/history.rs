@@ -0,0 +1,295 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+
+use super::{IntComputer, IntComputerState, IntcodeError, Memory, Queue, Value, ValueType};
+
+/// What it takes to undo one executed instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delta<V = ValueType> {
+    pub pc: usize,
+    pub rel_base: usize,
+    pub state: IntComputerState,
+    /// Address written and the value it held before.
+    pub write: Option<(usize, V)>,
+    pub input: Option<V>,
+    pub output: Option<V>,
+}
+
+/// Why `IntComputer::goto_step` did not reach its step.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GotoError<V = ValueType> {
+    /// The history reaches back to step `oldest` only, the machine was left
+    /// where it was.
+    TooShort { oldest: u64 },
+    /// The output of step `step` was already taken from the output queue and
+    /// cannot be undone, the machine was left where it was.
+    OutputTaken { step: u64 },
+    /// Running forward ended at step `reached` in `state`.
+    Stalled {
+        reached: u64,
+        state: IntComputerState,
+    },
+    /// Running forward failed.
+    Failed(IntcodeError<V>),
+}
+
+impl<V: Value> fmt::Display for GotoError<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GotoError::TooShort { oldest } => write!(f, "history only reaches step {}", oldest),
+            GotoError::OutputTaken { step } => {
+                write!(f, "output of step {} was already taken", step)
+            }
+            GotoError::Stalled { reached, state } => {
+                write!(f, "stopped at step {} in state {:?}", reached, state)
+            }
+            GotoError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl<V: Value> Error for GotoError<V> {}
+
+/// Undo log of the most recent `capacity` instructions.
+#[derive(Debug, Clone)]
+pub(super) struct History<V> {
+    capacity: usize,
+    deltas: VecDeque<Delta<V>>,
+}
+
+impl<V> History<V> {
+    pub(super) fn new(capacity: usize) -> Self {
+        History {
+            capacity,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    pub(super) fn push(&mut self, delta: Delta<V>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.deltas.len() == self.capacity {
+            self.deltas.pop_front();
+        }
+        self.deltas.push_back(delta);
+    }
+}
+
+impl<M, I, O> IntComputer<M, I, O>
+where
+    M: Memory,
+    I: super::InputSource<M::Value>,
+    O: super::OutputSink<M::Value>,
+{
+    /// Starts recording undo information for the next `capacity`
+    /// instructions, older entries are dropped once the log is full.
+    pub fn enable_history(&mut self, capacity: usize) {
+        self.history = Some(History::new(capacity));
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Number of instructions that can currently be undone.
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, |h| h.deltas.len())
+    }
+
+    /// Step count of the most recent recorded instruction that wrote to
+    /// `addr`, the place to look when a value turns out wrong.
+    pub fn last_write(&self, addr: usize) -> Option<u64> {
+        let deltas = &self.history.as_ref()?.deltas;
+        let pos = deltas
+            .iter()
+            .rposition(|d| matches!(d.write, Some((a, _)) if a == addr))?;
+        Some(self.steps - (deltas.len() - pos) as u64)
+    }
+}
+
+impl<M: Memory> IntComputer<M, Queue<M::Value>, Queue<M::Value>> {
+    /// Undoes the last executed instruction, including the input it consumed
+    /// and the output it produced. Returns false when there is nothing left
+    /// to undo, or when the output of the instruction was already taken from
+    /// the queue.
+    pub fn step_back(&mut self) -> bool {
+        let history = match self.history.as_mut() {
+            Some(history) => history,
+            None => return false,
+        };
+        let undoable = match history.deltas.back() {
+            Some(delta) => delta.output.is_none() || self.output.back() == delta.output.as_ref(),
+            None => false,
+        };
+        if !undoable {
+            return false;
+        }
+        let delta = history.deltas.pop_back().unwrap();
+        if let Some((addr, old)) = delta.write {
+            self.mem.write(addr, old);
+        }
+        if let Some(x) = delta.input {
+            self.input.push_front(x);
+        }
+        if delta.output.is_some() {
+            self.output.pop_back();
+            self.outputs -= 1;
+        }
+        self.pc = delta.pc;
+        self.rel_base = delta.rel_base;
+        self.state = delta.state;
+        self.steps -= 1;
+        true
+    }
+
+    /// Moves to the point where `steps` instructions have executed, backwards
+    /// through the history or forwards by running. Going back either reaches
+    /// the step or leaves the machine untouched.
+    pub fn goto_step(&mut self, steps: u64) -> Result<(), GotoError<M::Value>> {
+        if self.steps > steps {
+            let back = (self.steps - steps) as usize;
+            let deltas = self.history.as_ref().map(|h| &h.deltas);
+            let len = deltas.map_or(0, |d| d.len());
+            if back > len {
+                return Err(GotoError::TooShort {
+                    oldest: self.steps - len as u64,
+                });
+            }
+            // outputs are undone newest first and must still be queued
+            let mut queued = self.output.iter().rev();
+            for (i, delta) in deltas.unwrap().iter().rev().take(back).enumerate() {
+                if delta.output.is_some() && queued.next() != delta.output.as_ref() {
+                    return Err(GotoError::OutputTaken {
+                        step: self.steps - i as u64 - 1,
+                    });
+                }
+            }
+            while self.steps > steps {
+                self.step_back();
+            }
+        }
+        if self.steps < steps {
+            let remaining = (steps - self.steps) as usize;
+            let state = self.run_steps(remaining).map_err(GotoError::Failed)?;
+            if self.steps < steps {
+                return Err(GotoError::Stalled {
+                    reached: self.steps,
+                    state,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int_code::assemble;
+
+    fn machine() -> IntComputer {
+        let program = assemble(
+            "
+            loop: IN   [x]
+                  ADD  [x], [sum], [sum]
+                  OUT  [sum]
+                  JT   #1, #loop
+            x:    DATA 0
+            sum:  DATA 0
+            ",
+        )
+        .unwrap();
+        let mut computer = IntComputer::new(program);
+        computer.enable_history(100);
+        for x in 1..=3 {
+            computer.push_input(x);
+        }
+        computer
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut computer = machine();
+        computer.run().unwrap();
+        let end = computer.snapshot();
+        assert_eq!(computer.steps(), 12);
+
+        assert!(computer.step_back());
+        assert!(computer.step_back());
+        assert_eq!(computer.pc(), 6);
+        assert_eq!(computer.output().len(), 2);
+
+        assert_eq!(computer.goto_step(4), Ok(()));
+        assert_eq!(
+            computer.output().iter().copied().collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert_eq!(computer.input().len(), 2);
+        assert_eq!(computer.memory().read(12), 1);
+
+        assert_eq!(computer.goto_step(0), Ok(()));
+        assert!(!computer.step_back());
+        assert_eq!(computer.memory().read(11), 0);
+
+        computer.run().unwrap();
+        assert_eq!(computer.snapshot(), end);
+    }
+
+    #[test]
+    fn test_last_write() {
+        let mut computer = machine();
+        computer.run().unwrap();
+
+        assert_eq!(computer.last_write(12), Some(9));
+        assert_eq!(computer.last_write(11), Some(8));
+        assert_eq!(computer.last_write(0), None);
+    }
+
+    #[test]
+    fn test_history_capacity() {
+        let mut computer = machine();
+        computer.enable_history(5);
+        computer.run().unwrap();
+
+        assert_eq!(computer.history_len(), 5);
+        assert_eq!(
+            computer.goto_step(6),
+            Err(GotoError::TooShort { oldest: 7 })
+        );
+        assert_eq!(computer.steps(), 12);
+        assert_eq!(
+            computer.goto_step(20),
+            Err(GotoError::Stalled {
+                reached: 12,
+                state: IntComputerState::AwaitingInput
+            })
+        );
+
+        let mut computer = machine();
+        computer.enable_history(0);
+        computer.run().unwrap();
+        assert_eq!(computer.history_len(), 0);
+        assert!(!computer.step_back());
+    }
+
+    #[test]
+    fn test_taken_output() {
+        let mut computer = machine();
+        computer.run().unwrap();
+        assert_eq!(computer.get_output(), Some(1));
+
+        // the last outputs are still queued, the first one is gone
+        assert_eq!(
+            computer.goto_step(2),
+            Err(GotoError::OutputTaken { step: 2 })
+        );
+        assert_eq!(computer.steps(), 12);
+        assert_eq!(computer.goto_step(3), Ok(()));
+        assert_eq!(computer.outputs(), 1);
+        assert!(computer.output().is_empty());
+        assert!(!computer.step_back());
+        assert_eq!(computer.steps(), 3);
+    }
+}
@@ -0,0 +1,156 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+use std::sync::mpsc::{Receiver, Sender};
+
+use super::{Value, ValueType};
+
+/// Where an input instruction takes its value from.
+pub trait InputSource<V = ValueType> {
+    /// Next value, `None` leaves the machine `AwaitingInput` on the
+    /// instruction until a later call provides one.
+    fn next_input(&mut self) -> Option<V>;
+}
+
+/// Where an output instruction sends its value.
+pub trait OutputSink<V = ValueType> {
+    fn send_output(&mut self, value: V);
+}
+
+impl<V> InputSource<V> for VecDeque<V> {
+    fn next_input(&mut self) -> Option<V> {
+        self.pop_front()
+    }
+}
+
+impl<V> OutputSink<V> for VecDeque<V> {
+    fn send_output(&mut self, value: V) {
+        self.push_back(value);
+    }
+}
+
+/// A queue shared between machines on the same thread, handing one
+/// computer's output sink to another as its input source wires them together.
+pub type SharedQueue<V = ValueType> = Rc<RefCell<VecDeque<V>>>;
+
+impl<V> InputSource<V> for SharedQueue<V> {
+    fn next_input(&mut self) -> Option<V> {
+        self.borrow_mut().pop_front()
+    }
+}
+
+impl<V> OutputSink<V> for SharedQueue<V> {
+    fn send_output(&mut self, value: V) {
+        self.borrow_mut().push_back(value);
+    }
+}
+
+/// Blocks until a value arrives, a closed channel reads as no input.
+impl<V> InputSource<V> for Receiver<V> {
+    fn next_input(&mut self) -> Option<V> {
+        self.recv().ok()
+    }
+}
+
+/// Values sent after the receiving side hung up are dropped.
+impl<V> OutputSink<V> for Sender<V> {
+    fn send_output(&mut self, value: V) {
+        let _ = self.send(value);
+    }
+}
+
+/// Input produced by a closure.
+pub struct FnInput<F>(pub F);
+
+impl<V, F: FnMut() -> Option<V>> InputSource<V> for FnInput<F> {
+    fn next_input(&mut self) -> Option<V> {
+        (self.0)()
+    }
+}
+
+/// Output handed to a closure, e.g. a game state callback.
+pub struct FnOutput<F>(pub F);
+
+impl<V, F: FnMut(V)> OutputSink<V> for FnOutput<F> {
+    fn send_output(&mut self, value: V) {
+        (self.0)(value)
+    }
+}
+
+/// Input taken from an iterator, the machine blocks once it is exhausted.
+pub struct IterInput<T>(pub T);
+
+impl<T: Iterator> InputSource<T::Item> for IterInput<T> {
+    fn next_input(&mut self) -> Option<T::Item> {
+        self.0.next()
+    }
+}
+
+/// Feeds text line by line as ASCII codes, each line terminated by 10.
+pub struct AsciiInput<R> {
+    reader: R,
+    line: VecDeque<u8>,
+}
+
+impl<R: BufRead> AsciiInput<R> {
+    pub fn new(reader: R) -> Self {
+        AsciiInput {
+            reader,
+            line: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: BufRead, V: Value> InputSource<V> for AsciiInput<R> {
+    fn next_input(&mut self) -> Option<V> {
+        if self.line.is_empty() {
+            let mut line = String::new();
+            match self.reader.read_line(&mut line) {
+                Ok(n) if n > 0 => (),
+                _ => return None,
+            }
+            let line = line.trim_end_matches(&['\n', '\r'][..]);
+            self.line.extend(line.bytes());
+            self.line.push_back(b'\n');
+        }
+        self.line.pop_front().map(|b| V::from(b.into()))
+    }
+}
+
+/// Writes ASCII outputs as text, values outside of ASCII as numbers on their
+/// own line.
+pub struct AsciiOutput<W>(pub W);
+
+impl<W: Write, V: Value> OutputSink<V> for AsciiOutput<W> {
+    fn send_output(&mut self, value: V) {
+        let _ = match value.to_i64() {
+            Some(x) if (0..128).contains(&x) => self.0.write_all(&[x as u8]),
+            _ => writeln!(self.0, "{}", value),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii_input() {
+        let mut input = AsciiInput::new("hi\r\nx\n".as_bytes());
+        let mut result: Vec<ValueType> = Vec::new();
+        while let Some(x) = input.next_input() {
+            result.push(x);
+        }
+        assert_eq!(result, vec![104, 105, 10, 120, 10]);
+    }
+
+    #[test]
+    fn test_ascii_output() {
+        let mut output = AsciiOutput(Vec::new());
+        for &x in &[111, 107, 10, 1234 as ValueType] {
+            output.send_output(x);
+        }
+        assert_eq!(String::from_utf8(output.0).unwrap(), "ok\n1234\n");
+    }
+}
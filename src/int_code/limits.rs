@@ -0,0 +1,256 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use super::{InputSource, IntComputer, IntcodeError, Memory, OutputSink};
+
+/// Resources a machine may use, `None` leaves a resource unbounded. Set the
+/// fields needed and leave the rest to `..Limits::default()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Instructions executed over the lifetime of the machine.
+    pub max_steps: Option<u64>,
+    /// Addresses from this one up are off limits, for instructions and their
+    /// parameter words as well as for operands.
+    pub max_memory: Option<usize>,
+    /// Wall time of a single `run`, `run_until` or `run_steps` call, each
+    /// call starts the clock anew and `step` is not timed at all. It is
+    /// checked every `TIME_CHECK_INTERVAL` instructions.
+    pub max_time: Option<Duration>,
+    /// Values output over the lifetime of the machine.
+    pub max_output: Option<u64>,
+}
+
+/// Instructions executed between two looks at the clock.
+pub const TIME_CHECK_INTERVAL: u64 = 1 << 10;
+
+/// The limit that was hit, with its configured value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Steps(u64),
+    Memory(usize),
+    Time(Duration),
+    Output(u64),
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Limit::Steps(n) => write!(f, "step limit {}", n),
+            Limit::Memory(n) => write!(f, "memory limit {}", n),
+            Limit::Time(t) => write!(f, "time limit {:?}", t),
+            Limit::Output(n) => write!(f, "output limit {}", n),
+        }
+    }
+}
+
+impl<M, I, O> IntComputer<M, I, O>
+where
+    M: Memory,
+    I: InputSource<M::Value>,
+    O: OutputSink<M::Value>,
+{
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub(super) fn limit_error(&self, limit: Limit) -> IntcodeError<M::Value> {
+        IntcodeError::LimitExceeded {
+            pc: self.pc,
+            steps: self.steps,
+            limit,
+        }
+    }
+
+    /// Checks the limits the run loop is responsible for before the next
+    /// instruction executes.
+    pub(super) fn check_limits(
+        &self,
+        deadline: Option<Instant>,
+    ) -> Result<(), IntcodeError<M::Value>> {
+        let limits = &self.limits;
+        if let Some(max) = limits.max_steps.filter(|&max| self.steps >= max) {
+            return Err(self.limit_error(Limit::Steps(max)));
+        }
+        if let Some(max) = limits.max_memory.filter(|&max| self.pc >= max) {
+            return Err(self.limit_error(Limit::Memory(max)));
+        }
+        if let Some(deadline) = deadline {
+            if self.steps.is_multiple_of(TIME_CHECK_INTERVAL) && Instant::now() >= deadline {
+                return Err(self.limit_error(Limit::Time(limits.max_time.unwrap())));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that all `len` words of the instruction at the pc lie below
+    /// the memory limit.
+    pub(super) fn check_fetch(&self, len: usize) -> Result<(), IntcodeError<M::Value>> {
+        match self.limits.max_memory {
+            Some(max) if self.pc + len > max => Err(self.limit_error(Limit::Memory(max))),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int_code::{IntComputerState, Opcode};
+
+    fn endless() -> IntComputer {
+        // outputs 7 forever
+        IntComputer::new(vec![104, 7, 1105, 1, 0])
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let mut computer = endless();
+        computer.set_limits(Limits {
+            max_steps: Some(101),
+            ..Limits::default()
+        });
+
+        let err = computer.run().unwrap_err();
+        assert_eq!(
+            err,
+            IntcodeError::LimitExceeded {
+                pc: 2,
+                steps: 101,
+                limit: Limit::Steps(101)
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "step limit 101 exceeded @ 0002 after 101 steps"
+        );
+        assert_eq!(computer.output().len(), 51);
+
+        // single steps count against the same limit
+        let mut computer = endless();
+        computer.set_limits(Limits {
+            max_steps: Some(2),
+            ..Limits::default()
+        });
+        assert_eq!(computer.step(), Ok(Opcode::Output));
+        assert_eq!(computer.step(), Ok(Opcode::JumpTrue));
+        assert!(matches!(
+            computer.step(),
+            Err(IntcodeError::LimitExceeded {
+                limit: Limit::Steps(2),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_output_limit() {
+        let mut computer = endless();
+        computer.set_limits(Limits {
+            max_output: Some(3),
+            ..Limits::default()
+        });
+
+        let err = computer.run().unwrap_err();
+        assert!(matches!(
+            err,
+            IntcodeError::LimitExceeded {
+                pc: 0,
+                steps: 6,
+                limit: Limit::Output(3)
+            }
+        ));
+        assert_eq!(computer.output().len(), 3);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let limits = Limits {
+            max_memory: Some(100),
+            ..Limits::default()
+        };
+        let mut computer = IntComputer::new(vec![1101, 1, 1, 100, 99]);
+        computer.set_limits(limits);
+        let err = computer.run().unwrap_err();
+        assert!(matches!(
+            err,
+            IntcodeError::LimitExceeded {
+                pc: 0,
+                limit: Limit::Memory(100),
+                ..
+            }
+        ));
+
+        // a jump past the limit trips at the jump
+        let mut computer = IntComputer::new(vec![1105, 1, 100]);
+        computer.set_limits(limits);
+        let err = computer.run().unwrap_err();
+        assert!(matches!(
+            err,
+            IntcodeError::LimitExceeded {
+                pc: 0,
+                steps: 0,
+                ..
+            }
+        ));
+
+        // running off the end trips before the next fetch
+        let mut computer = IntComputer::new(vec![104, 1]);
+        computer.set_limits(Limits {
+            max_memory: Some(2),
+            ..Limits::default()
+        });
+        let err = computer.run().unwrap_err();
+        assert!(matches!(
+            err,
+            IntcodeError::LimitExceeded {
+                pc: 2,
+                steps: 1,
+                ..
+            }
+        ));
+
+        // so does an instruction whose parameter words cross it
+        let mut computer = IntComputer::new(vec![1105, 1, 8, 0, 0, 0, 0, 0, 104, 5]);
+        computer.set_limits(Limits {
+            max_memory: Some(9),
+            ..Limits::default()
+        });
+        let err = computer.run().unwrap_err();
+        assert!(matches!(
+            err,
+            IntcodeError::LimitExceeded {
+                pc: 8,
+                steps: 1,
+                ..
+            }
+        ));
+        assert!(computer.output().is_empty());
+
+        let mut computer = IntComputer::new(vec![1101, 1, 1, 99, 4, 99, 99]);
+        computer.set_limits(limits);
+        assert_eq!(computer.run(), Ok(IntComputerState::Stopped));
+    }
+
+    #[test]
+    fn test_time_limit() {
+        let mut computer = endless();
+        computer.set_limits(Limits {
+            max_time: Some(Duration::from_millis(20)),
+            ..Limits::default()
+        });
+
+        let err = computer.run().unwrap_err();
+        assert!(matches!(
+            err,
+            IntcodeError::LimitExceeded {
+                limit: Limit::Time(_),
+                ..
+            }
+        ));
+        assert!(computer.steps().is_multiple_of(TIME_CHECK_INTERVAL));
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use super::{Value, ValueType};
+
+/// Storage backend of an `IntComputer`.
+///
+/// Memory is conceptually unbounded: reading an address that was never
+/// written yields 0 and writing anywhere below `capacity` grows the backing
+/// store as needed.
+pub trait Memory: Clone {
+    type Value: Value;
+
+    fn read(&self, addr: usize) -> Self::Value;
+    fn write(&mut self, addr: usize, value: Self::Value);
+    /// One past the highest address that may hold a non-zero value.
+    fn extent(&self) -> usize;
+    /// Addresses from this one up cannot be written to.
+    fn capacity(&self) -> usize {
+        usize::MAX
+    }
+    /// Contiguous blocks `(start, values)` covering every non-zero cell, in
+    /// address order.
+    fn segments(&self) -> Vec<(usize, Vec<Self::Value>)>;
+}
+
+/// Splits `values` into runs of non-zero cells.
+fn nonzero_runs<V: Value>(start: usize, values: &[V]) -> Vec<(usize, Vec<V>)> {
+    let mut runs: Vec<(usize, Vec<V>)> = Vec::new();
+    for (i, x) in values.iter().enumerate() {
+        if x.is_zero() {
+            continue;
+        }
+        match runs.last_mut() {
+            Some((s, run)) if *s + run.len() == start + i => run.push(x.clone()),
+            _ => runs.push((start + i, vec![x.clone()])),
+        }
+    }
+    runs
+}
+
+const PAGE_BITS: usize = 10;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+/// Pages below this index are found by indexing instead of hashing, which is
+/// where stacks and heaps of real programs live.
+const NEAR_PAGES: usize = 1024;
+
+type Page<V> = Box<[V]>;
+
+/// The program as a dense segment starting at address 0, everything past it
+/// in lazily allocated pages.
+///
+/// Cloning only copies the program and the pages that were actually touched.
+#[derive(Debug, Clone, Default)]
+pub struct PagedMemory<V = ValueType> {
+    program: Vec<V>,
+    near: Vec<Option<Page<V>>>,
+    far: HashMap<usize, Page<V>>,
+}
+
+impl<V> PagedMemory<V> {
+    pub fn new(program: Vec<V>) -> Self {
+        PagedMemory {
+            program,
+            near: Vec::new(),
+            far: HashMap::new(),
+        }
+    }
+
+    /// Number of pages allocated beyond the program segment.
+    pub fn pages(&self) -> usize {
+        self.near.iter().flatten().count() + self.far.len()
+    }
+
+    fn split(&self, addr: usize) -> (usize, usize) {
+        let addr = addr - self.program.len();
+        (addr >> PAGE_BITS, addr & (PAGE_SIZE - 1))
+    }
+
+    #[inline]
+    fn page(&self, page: usize) -> Option<&Page<V>> {
+        if page < NEAR_PAGES {
+            self.near.get(page)?.as_ref()
+        } else {
+            self.far.get(&page)
+        }
+    }
+
+    /// Allocated pages with their index, in index order.
+    fn sorted_pages(&self) -> Vec<(usize, &Page<V>)> {
+        let near = self.near.iter().enumerate();
+        let mut pages: Vec<_> = near.filter_map(|(i, p)| Some((i, p.as_ref()?))).collect();
+        let mut far: Vec<_> = self.far.iter().map(|(&i, p)| (i, p)).collect();
+        far.sort_by_key(|&(i, _)| i);
+        pages.extend(far);
+        pages
+    }
+}
+
+impl<V: Value> PagedMemory<V> {
+    fn page_mut(&mut self, page: usize) -> &mut Page<V> {
+        let new_page = || vec![V::default(); PAGE_SIZE].into_boxed_slice();
+        if page < NEAR_PAGES {
+            if page >= self.near.len() {
+                self.near.resize_with(page + 1, || None);
+            }
+            self.near[page].get_or_insert_with(new_page)
+        } else {
+            self.far.entry(page).or_insert_with(new_page)
+        }
+    }
+}
+
+impl<V: Value> Memory for PagedMemory<V> {
+    type Value = V;
+
+    #[inline]
+    fn read(&self, addr: usize) -> V {
+        if let Some(x) = self.program.get(addr) {
+            return x.clone();
+        }
+        let (page, offset) = self.split(addr);
+        self.page(page).map_or_else(V::default, |p| p[offset].clone())
+    }
+
+    #[inline]
+    fn write(&mut self, addr: usize, value: V) {
+        if let Some(x) = self.program.get_mut(addr) {
+            *x = value;
+            return;
+        }
+        let (page, offset) = self.split(addr);
+        if value.is_zero() && self.page(page).is_none() {
+            return;
+        }
+        self.page_mut(page)[offset] = value;
+    }
+
+    fn extent(&self) -> usize {
+        match self.sorted_pages().last() {
+            Some(&(page, _)) => self.program.len() + (page + 1) * PAGE_SIZE,
+            None => self.program.len(),
+        }
+    }
+
+    /// The program segment comes first and in one piece, zeros included.
+    fn segments(&self) -> Vec<(usize, Vec<V>)> {
+        let mut segments = vec![(0, self.program.clone())];
+        for (page, values) in self.sorted_pages() {
+            let start = self.program.len() + page * PAGE_SIZE;
+            segments.extend(nonzero_runs(start, &values[..]));
+        }
+        segments
+    }
+}
+
+impl<V> From<Vec<V>> for PagedMemory<V> {
+    fn from(program: Vec<V>) -> Self {
+        PagedMemory::new(program)
+    }
+}
+
+/// Addresses a `Vec` memory can be written to, it grows to hold the highest
+/// one written so a single far store would allocate all the space below it.
+pub const VEC_CAPACITY: usize = 1 << 24;
+
+/// Plain vector that grows on write, useful when the program touches a small
+/// contiguous region only.
+impl<V: Value> Memory for Vec<V> {
+    type Value = V;
+
+    #[inline]
+    fn read(&self, addr: usize) -> V {
+        self.get(addr).cloned().unwrap_or_default()
+    }
+
+    #[inline]
+    fn write(&mut self, addr: usize, value: V) {
+        if addr >= self.len() {
+            if value.is_zero() {
+                return;
+            }
+            assert!(addr < VEC_CAPACITY, "address {} beyond vector memory", addr);
+            self.resize(addr + 1, V::default());
+        }
+        self[addr] = value;
+    }
+
+    fn extent(&self) -> usize {
+        self.len()
+    }
+
+    fn capacity(&self) -> usize {
+        VEC_CAPACITY
+    }
+
+    fn segments(&self) -> Vec<(usize, Vec<V>)> {
+        vec![(0, self.clone())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int_code::{IntComputer, IntcodeError};
+
+    #[test]
+    fn test_paged_memory_reads_zero() {
+        let mem: PagedMemory = PagedMemory::new(vec![1, 2, 3]);
+
+        assert_eq!(mem.read(1), 2);
+        assert_eq!(mem.read(3), 0);
+        assert_eq!(mem.read(1 << 40), 0);
+        assert_eq!(mem.pages(), 0);
+    }
+
+    #[test]
+    fn test_paged_memory_grows_on_write() {
+        let mut mem: PagedMemory = PagedMemory::new(vec![1, 2, 3]);
+        mem.write(0, 7);
+        mem.write(10, 4);
+        mem.write(1 << 40, 5);
+        mem.write(1 << 41, 0);
+
+        assert_eq!(mem.read(0), 7);
+        assert_eq!(mem.read(10), 4);
+        assert_eq!(mem.read(1 << 40), 5);
+        assert_eq!(mem.pages(), 2);
+        assert_eq!(
+            mem.segments(),
+            vec![(0, vec![7, 2, 3]), (10, vec![4]), (1 << 40, vec![5])]
+        );
+
+        let copy = mem.clone();
+        mem.write(10, 6);
+        assert_eq!(copy.read(10), 4);
+    }
+
+    #[test]
+    fn test_vec_memory_capacity() {
+        let program: Vec<ValueType> = vec![1101, 1, 1, 1 << 40, 99];
+        let mut computer = IntComputer::with_memory(program);
+        let err = computer.run().unwrap_err();
+        assert_eq!(
+            err,
+            IntcodeError::OutOfBounds {
+                pc: 0,
+                word: 1101,
+                addr: 1 << 40
+            }
+        );
+
+        // reads past the end are zero as for any memory
+        let program: Vec<ValueType> = vec![1001, 1 << 40, 5, 9, 4, 9, 99];
+        let mut computer = IntComputer::with_memory(program);
+        computer.run().unwrap();
+        assert_eq!(computer.get_output(), Some(5));
+        assert_eq!(computer.memory().len(), 10);
+    }
+}
@@ -0,0 +1,471 @@
+//! Many machines exchanging values on a single thread.
+//!
+//! Machines take turns in the order they were added. A turn lasts until the
+//! machine blocks on input, stops or has used up its quantum of
+//! instructions, and whatever it output is delivered before the next machine
+//! runs, so a network always runs the same way.
+//!
+//! Outputs either follow edges, each value going to every target of the
+//! sender in the order the edges were added, or are read as addressed
+//! packets `dest, x, y` whose `x, y` are queued at machine `dest`. Packets
+//! for the monitor address are kept by the monitor, which passes the last
+//! one on to a chosen machine whenever the network goes idle.
+
+use std::error::Error;
+use std::fmt;
+
+use super::{IntComputer, IntComputerState, IntcodeError, Memory, PagedMemory, Value};
+
+/// Instructions a machine may execute per turn unless set otherwise.
+pub const DEFAULT_QUANTUM: usize = 1 << 10;
+
+/// A packet as sent by machine `from`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet<V> {
+    pub from: usize,
+    pub dest: V,
+    pub x: V,
+    pub y: V,
+}
+
+impl<V: Value> fmt::Display for Packet<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {}: ({}, {})",
+            self.from, self.dest, self.x, self.y
+        )
+    }
+}
+
+/// A machine of the network failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeError<V> {
+    pub node: usize,
+    pub error: IntcodeError<V>,
+}
+
+impl<V: Value> fmt::Display for NodeError<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "node {}: {}", self.node, self.error)
+    }
+}
+
+impl<V: Value> Error for NodeError<V> {}
+
+/// What the last round amounted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    /// Some machine took input, output or ran out of its quantum.
+    Busy,
+    /// Every machine waited for input that never came, then the monitor
+    /// passed on a packet.
+    Woken,
+    /// Every machine waits for input and nothing is on its way.
+    Idle,
+    /// Every machine has stopped.
+    Stopped,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Routing {
+    Edges,
+    Packets,
+}
+
+#[derive(Debug, Clone)]
+struct Monitor<V> {
+    addr: V,
+    wake: usize,
+    received: Vec<Packet<V>>,
+    released: Vec<(V, V)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Network<M: Memory = PagedMemory> {
+    nodes: Vec<IntComputer<M>>,
+    edges: Vec<Vec<usize>>,
+    routing: Routing,
+    quantum: usize,
+    idle_input: Option<M::Value>,
+    monitor: Option<Monitor<M::Value>>,
+    undeliverable: Vec<Packet<M::Value>>,
+    activity: Activity,
+    rounds: u64,
+}
+
+impl<M: Memory> Default for Network<M> {
+    fn default() -> Self {
+        Network {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            routing: Routing::Edges,
+            quantum: DEFAULT_QUANTUM,
+            idle_input: None,
+            monitor: None,
+            undeliverable: Vec::new(),
+            activity: Activity::Busy,
+            rounds: 0,
+        }
+    }
+}
+
+impl<M: Memory> Network<M> {
+    /// An empty network routing along edges.
+    pub fn new() -> Self {
+        Network::default()
+    }
+
+    /// `n` copies of `program` exchanging packets, each told its address as
+    /// its first input. Reading from an empty queue yields -1.
+    pub fn packet_switched(program: &IntComputer<M>, n: usize) -> Self {
+        let mut network = Network {
+            routing: Routing::Packets,
+            idle_input: Some((-1).into()),
+            ..Network::default()
+        };
+        for addr in 0..n {
+            let node = network.add(program.clone());
+            network.send(node, (addr as i64).into());
+        }
+        network
+    }
+
+    /// Adds a machine and returns its index.
+    pub fn add(&mut self, machine: IntComputer<M>) -> usize {
+        self.nodes.push(machine);
+        self.edges.push(Vec::new());
+        self.nodes.len() - 1
+    }
+
+    /// Sends every output of `from` to `to` as well.
+    pub fn connect(&mut self, from: usize, to: usize) {
+        assert!(to < self.nodes.len(), "no node {}", to);
+        self.edges[from].push(to);
+    }
+
+    /// Connects `from` to every other machine added so far.
+    pub fn broadcast(&mut self, from: usize) {
+        for to in (0..self.nodes.len()).filter(|&to| to != from) {
+            self.connect(from, to);
+        }
+    }
+
+    /// Queues `value` as input of machine `to`.
+    pub fn send(&mut self, to: usize, value: M::Value) {
+        self.nodes[to].push_input(value);
+    }
+
+    pub fn set_quantum(&mut self, quantum: usize) {
+        self.quantum = quantum.max(1);
+    }
+
+    /// Value a machine reads instead of blocking once per turn, `None` lets
+    /// it block.
+    pub fn set_idle_input(&mut self, value: Option<M::Value>) {
+        self.idle_input = value;
+    }
+
+    /// Keeps packets sent to `addr` and passes the last one to machine `wake`
+    /// when the network is idle.
+    pub fn set_monitor(&mut self, addr: M::Value, wake: usize) {
+        self.monitor = Some(Monitor {
+            addr,
+            wake,
+            received: Vec::new(),
+            released: Vec::new(),
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn node(&self, index: usize) -> &IntComputer<M> {
+        &self.nodes[index]
+    }
+
+    pub fn node_mut(&mut self, index: usize) -> &mut IntComputer<M> {
+        &mut self.nodes[index]
+    }
+
+    /// Packets the monitor received, oldest first.
+    pub fn monitor_received(&self) -> &[Packet<M::Value>] {
+        self.monitor.as_ref().map_or(&[], |m| &m.received[..])
+    }
+
+    /// Values the monitor passed on, oldest first.
+    pub fn monitor_released(&self) -> &[(M::Value, M::Value)] {
+        self.monitor.as_ref().map_or(&[], |m| &m.released[..])
+    }
+
+    /// Packets addressed to neither a machine nor the monitor.
+    pub fn undeliverable(&self) -> &[Packet<M::Value>] {
+        &self.undeliverable
+    }
+
+    pub fn activity(&self) -> Activity {
+        self.activity
+    }
+
+    pub fn rounds(&self) -> u64 {
+        self.rounds
+    }
+
+    /// Gives every machine one turn.
+    pub fn round(&mut self) -> Result<Activity, NodeError<M::Value>> {
+        let mut idle = true;
+        for index in 0..self.nodes.len() {
+            idle &= !self.turn(index)?;
+            self.deliver(index);
+        }
+        self.rounds += 1;
+
+        // input queued at a stopped machine is never taken
+        let pending = self
+            .nodes
+            .iter()
+            .any(|n| n.get_state() != IntComputerState::Stopped && !n.input().is_empty());
+        self.activity = if self
+            .nodes
+            .iter()
+            .all(|n| n.get_state() == IntComputerState::Stopped)
+        {
+            Activity::Stopped
+        } else if !idle || pending {
+            Activity::Busy
+        } else if self.wake() {
+            Activity::Woken
+        } else {
+            Activity::Idle
+        };
+        Ok(self.activity)
+    }
+
+    /// Runs rounds until `pred` holds before the next one, or until the
+    /// network is idle or stopped.
+    pub fn run_until<F>(&mut self, mut pred: F) -> Result<Activity, NodeError<M::Value>>
+    where
+        F: FnMut(&Self) -> bool,
+    {
+        while !pred(self) {
+            if let Activity::Idle | Activity::Stopped = self.round()? {
+                break;
+            }
+        }
+        Ok(self.activity)
+    }
+
+    pub fn run(&mut self) -> Result<Activity, NodeError<M::Value>> {
+        self.run_until(|_| false)
+    }
+
+    /// Runs the turn of one machine, returns whether it did anything.
+    fn turn(&mut self, index: usize) -> Result<bool, NodeError<M::Value>> {
+        let node = &mut self.nodes[index];
+        if node.get_state() == IntComputerState::Stopped {
+            return Ok(false);
+        }
+        let had_input = !node.input().is_empty();
+        let (steps, outputs) = (node.steps(), node.outputs());
+        let mut polled = false;
+        loop {
+            let used = (node.steps() - steps) as usize;
+            if used >= self.quantum {
+                break;
+            }
+            let state = node
+                .run_steps(self.quantum - used)
+                .map_err(|error| NodeError { node: index, error })?;
+            match (state, &self.idle_input) {
+                (IntComputerState::OutputReady, _) => (),
+                (IntComputerState::AwaitingInput, Some(value)) if !polled => {
+                    node.push_input(value.clone());
+                    polled = true;
+                }
+                _ => break,
+            }
+        }
+        Ok(had_input || node.outputs() != outputs || node.get_state() == IntComputerState::Running)
+    }
+
+    /// Hands on what machine `from` output during its turn.
+    fn deliver(&mut self, from: usize) {
+        match self.routing {
+            Routing::Edges => {
+                if self.edges[from].is_empty() {
+                    return;
+                }
+                while let Some(value) = self.nodes[from].get_output() {
+                    for &to in &self.edges[from] {
+                        self.nodes[to].push_input(value.clone());
+                    }
+                }
+            }
+            Routing::Packets => {
+                while self.nodes[from].output().len() >= 3 {
+                    let out = self.nodes[from].output_mut();
+                    let mut next = || out.pop_front().unwrap();
+                    let packet = Packet {
+                        from,
+                        dest: next(),
+                        x: next(),
+                        y: next(),
+                    };
+                    self.route(packet);
+                }
+            }
+        }
+    }
+
+    fn route(&mut self, packet: Packet<M::Value>) {
+        let to = packet.dest.to_i64().filter(|&d| d >= 0).map(|d| d as usize);
+        match (to, &mut self.monitor) {
+            (Some(to), _) if to < self.nodes.len() => {
+                self.nodes[to].push_input(packet.x);
+                self.nodes[to].push_input(packet.y);
+            }
+            (_, Some(monitor)) if monitor.addr == packet.dest => monitor.received.push(packet),
+            _ => self.undeliverable.push(packet),
+        }
+    }
+
+    /// Lets the monitor pass on its last packet, returns whether it did.
+    fn wake(&mut self) -> bool {
+        let monitor = match &mut self.monitor {
+            Some(monitor) => monitor,
+            None => return false,
+        };
+        let last = match monitor.received.last() {
+            Some(packet) => (packet.x.clone(), packet.y.clone()),
+            None => return false,
+        };
+        if self.nodes[monitor.wake].get_state() == IntComputerState::Stopped {
+            return false;
+        }
+        self.nodes[monitor.wake].push_input(last.0.clone());
+        self.nodes[monitor.wake].push_input(last.1.clone());
+        monitor.released.push(last);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int_code::assemble;
+
+    fn machine(source: &str) -> IntComputer {
+        IntComputer::new(assemble(source).unwrap())
+    }
+
+    fn scaler(factor: i64) -> IntComputer {
+        machine(&format!(
+            "loop: IN [x]\nMUL [x], #{}, [x]\nOUT [x]\nJT #1, #loop\nx: DATA 0",
+            factor
+        ))
+    }
+
+    #[test]
+    fn test_edges() {
+        let mut network = Network::new();
+        let source = network.add(machine("OUT #1\nOUT #2\nOUT #3\nHLT"));
+        let double = network.add(scaler(2));
+        let triple = network.add(scaler(3));
+        let sum = network.add(machine(
+            "loop: IN [a]\nIN [b]\nADD [a], [b], [a]\nOUT [a]\nJT #1, #loop\na: DATA 0\nb: DATA 0",
+        ));
+        network.connect(source, double);
+        network.connect(source, triple);
+        network.connect(double, sum);
+        network.connect(triple, sum);
+
+        assert_eq!(network.run(), Ok(Activity::Idle));
+        assert_eq!(network.rounds(), 2);
+        // each scaler delivers all of its values within its turn
+        let outputs: Vec<_> = network.node(sum).output().iter().copied().collect();
+        assert_eq!(outputs, vec![6, 9, 15]);
+
+        // a failing machine is reported with its index
+        network.add(machine("DATA 98"));
+        network.broadcast(sum);
+        network.send(sum, 1);
+        network.send(sum, 2);
+        let err = network.run().unwrap_err();
+        assert_eq!(err.node, 4);
+        assert!(matches!(
+            err.error,
+            IntcodeError::InvalidOpcode { pc: 0, .. }
+        ));
+    }
+
+    #[test]
+    fn test_input_to_stopped() {
+        let mut network = Network::new();
+        let source = network.add(machine("OUT #1\nHLT"));
+        let sink = network.add(machine("HLT"));
+        let waiting = network.add(machine("IN [x]\nHLT\nx: DATA 0"));
+        network.connect(source, sink);
+
+        // the value left at the halted sink keeps nothing busy
+        assert_eq!(network.run(), Ok(Activity::Idle));
+        assert_eq!(network.node(sink).input().len(), 1);
+        assert_eq!(
+            network.node(waiting).get_state(),
+            IntComputerState::AwaitingInput
+        );
+    }
+
+    // node 0 sends (10, 20) to node 1, every node forwards what it receives
+    // to 255 after adding its own address to x
+    const NIC: &str = "
+              IN   [addr]
+              JT   [addr], #recv
+              OUT  #1
+              OUT  #10
+              OUT  #20
+        recv: IN   [x]
+              EQ   [x], #-1, [t]
+              JT   [t], #recv
+              IN   [y]
+              ADD  [x], [addr], [x]
+              OUT  #255
+              OUT  [x]
+              OUT  [y]
+              JT   #1, #recv
+        addr: DATA 0
+        x:    DATA 0
+        y:    DATA 0
+        t:    DATA 0
+    ";
+
+    #[test]
+    fn test_packets() {
+        let nic = machine(NIC);
+
+        let mut network = Network::packet_switched(&nic, 2);
+        assert_eq!(network.run(), Ok(Activity::Idle));
+        let expected = Packet {
+            from: 1,
+            dest: 255,
+            x: 11,
+            y: 20,
+        };
+        assert_eq!(network.undeliverable(), std::slice::from_ref(&expected));
+        assert_eq!(expected.to_string(), "1 -> 255: (11, 20)");
+
+        let mut network = Network::packet_switched(&nic, 2);
+        network.set_monitor(255, 0);
+        let state = network.run_until(|n| n.monitor_released().len() == 2);
+        assert_eq!(state, Ok(Activity::Woken));
+        assert_eq!(network.rounds(), 4);
+        assert_eq!(network.monitor_released(), &[(11, 20), (11, 20)]);
+        let senders: Vec<_> = network.monitor_received().iter().map(|p| p.from).collect();
+        assert_eq!(senders, vec![1, 0]);
+        assert!(network.undeliverable().is_empty());
+    }
+}
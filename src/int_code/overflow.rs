@@ -0,0 +1,17 @@
+/// What `ADD` and `MUL` do when the exact result does not fit the machine's
+/// value type. Types without a fixed width, like big integers, ignore it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Two's complement wrap around.
+    Wrapping,
+    /// Clamp to the smallest or largest representable value.
+    #[default]
+    Saturating,
+    /// Fail with `IntcodeError::Overflow`. The machine stays on the faulting
+    /// instruction with memory untouched, so it can be moved to a wider value
+    /// type with `IntComputer::widen` and resumed.
+    Checked,
+    /// Fails like `Checked` inside a single machine, a `Promoting` machine
+    /// then widens itself and carries on.
+    Promote,
+}
@@ -0,0 +1,303 @@
+//! Machines chained by channels, each running on a thread of its own.
+//!
+//! Stage `i` feeds stage `i + 1`. In a ring the last stage also feeds the
+//! first, in a series its output leaves the pipeline. Every stage is first
+//! given its phase setting, the first stage then gets the initial signal.
+//! A stage whose upstream is gone sees its input closed and returns
+//! `AwaitingInput`, so a halting or failing stage winds down the rest.
+//! When every stage still running waits for input with none on the way the
+//! stages give up the same way and report the deadlock. Set `Limits` on the
+//! template program to bound stages that never wait.
+
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{InputSource, IntComputer, IntComputerState, IntcodeError, Memory, OutputSink};
+
+/// What the stages of one run wait for, shared to spot a deadlock.
+struct Waits {
+    counts: Mutex<Counts>,
+    changed: Condvar,
+}
+
+struct Counts {
+    /// Stages still running.
+    live: usize,
+    /// Stages waiting for input.
+    waiting: usize,
+    /// Values sent that no stage has taken yet.
+    in_flight: usize,
+    deadlocked: bool,
+}
+
+impl Waits {
+    fn lock(&self) -> MutexGuard<'_, Counts> {
+        self.counts.lock().expect("pipeline stage panicked")
+    }
+}
+
+/// Takes input from the previous stage, giving up on a deadlock.
+struct Inlet<'w, V> {
+    input: Receiver<V>,
+    waits: &'w Waits,
+    deadlocked: bool,
+}
+
+impl<V> InputSource<V> for Inlet<'_, V> {
+    fn next_input(&mut self) -> Option<V> {
+        let mut counts = self.waits.lock();
+        loop {
+            // a stage woken by the deadlock may find its upstream gone already
+            if counts.deadlocked {
+                self.deadlocked = true;
+                return None;
+            }
+            match self.input.try_recv() {
+                Ok(value) => {
+                    counts.in_flight -= 1;
+                    return Some(value);
+                }
+                Err(TryRecvError::Disconnected) => return None,
+                Err(TryRecvError::Empty) => (),
+            }
+            if counts.waiting + 1 == counts.live && counts.in_flight == 0 {
+                counts.deadlocked = true;
+                self.waits.changed.notify_all();
+                continue;
+            }
+            counts.waiting += 1;
+            counts = self
+                .waits
+                .changed
+                .wait(counts)
+                .expect("pipeline stage panicked");
+            counts.waiting -= 1;
+        }
+    }
+}
+
+/// Forwards outputs to the next stage, remembering the last one.
+struct Tap<'w, V> {
+    next: Sender<V>,
+    waits: &'w Waits,
+    last: Option<V>,
+}
+
+impl<V: Clone> OutputSink<V> for Tap<'_, V> {
+    fn send_output(&mut self, value: V) {
+        self.last = Some(value.clone());
+        let mut counts = self.waits.lock();
+        // the next stage may have halted already
+        if self.next.send(value).is_ok() {
+            counts.in_flight += 1;
+            self.waits.changed.notify_all();
+        }
+    }
+}
+
+/// How one stage of a pipeline ended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageReport<V> {
+    pub phase: V,
+    pub steps: u64,
+    pub outputs: u64,
+    pub last_output: Option<V>,
+    pub elapsed: Duration,
+    /// Final state, `AwaitingInput` if the input closed or deadlocked.
+    pub result: Result<IntComputerState, IntcodeError<V>>,
+    /// Gave up waiting for input because every stage still running waited.
+    pub deadlocked: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineReport<V> {
+    /// Last output of the last stage.
+    pub signal: Option<V>,
+    pub stages: Vec<StageReport<V>>,
+}
+
+impl<V: PartialEq> PipelineReport<V> {
+    /// Whether every stage halted normally.
+    pub fn is_complete(&self) -> bool {
+        self.stages
+            .iter()
+            .all(|s| s.result == Ok(IntComputerState::Stopped))
+    }
+}
+
+/// A chain of copies of one program, see the module documentation.
+pub struct Pipeline<'a, M: Memory> {
+    program: &'a IntComputer<M>,
+    phases: Vec<M::Value>,
+    ring: bool,
+}
+
+impl<'a, M: Memory + Send> Pipeline<'a, M> {
+    /// One stage per phase, the last stage's output is the result.
+    pub fn series(program: &'a IntComputer<M>, phases: &[M::Value]) -> Self {
+        Pipeline {
+            program,
+            phases: phases.to_vec(),
+            ring: false,
+        }
+    }
+
+    /// One stage per phase, the last stage feeds back into the first.
+    pub fn ring(program: &'a IntComputer<M>, phases: &[M::Value]) -> Self {
+        Pipeline {
+            program,
+            phases: phases.to_vec(),
+            ring: true,
+        }
+    }
+
+    /// Runs all stages until they stop, `signal` is the second input of the
+    /// first stage.
+    pub fn run(&self, signal: M::Value) -> PipelineReport<M::Value> {
+        let (mut senders, receivers): (Vec<_>, Vec<_>) =
+            self.phases.iter().map(|_| channel()).unzip();
+        for (tx, phase) in senders.iter().zip(&self.phases) {
+            let _ = tx.send(phase.clone());
+        }
+        if let Some(first) = senders.first() {
+            let _ = first.send(signal);
+        }
+        let waits = Waits {
+            counts: Mutex::new(Counts {
+                live: self.phases.len(),
+                waiting: 0,
+                in_flight: self.phases.len() + senders.len().min(1),
+                deadlocked: false,
+            }),
+            changed: Condvar::new(),
+        };
+        // stage i writes to the input of stage i + 1
+        senders.rotate_left(1);
+        if !self.ring {
+            if let Some(last) = senders.last_mut() {
+                *last = channel().0;
+            }
+        }
+
+        let stages = thread::scope(|scope| {
+            let stages: Vec<_> = receivers
+                .into_iter()
+                .zip(senders)
+                .zip(&self.phases)
+                .map(|((input, next), phase)| {
+                    let machine = self.program.clone();
+                    let phase = phase.clone();
+                    let waits = &waits;
+                    scope.spawn(move || {
+                        let input = Inlet {
+                            input,
+                            waits,
+                            deadlocked: false,
+                        };
+                        let output = Tap {
+                            next,
+                            waits,
+                            last: None,
+                        };
+                        let mut machine = machine.rewire(input, output);
+                        let start = Instant::now();
+                        let result = machine.run();
+                        let report = StageReport {
+                            phase,
+                            steps: machine.steps(),
+                            outputs: machine.outputs(),
+                            last_output: machine.output().last.clone(),
+                            elapsed: start.elapsed(),
+                            result,
+                            deadlocked: machine.input().deadlocked,
+                        };
+                        let mut counts = waits.lock();
+                        counts.in_flight -= machine.input().input.try_iter().count();
+                        // dropping the machine hangs up on the next stage
+                        drop(machine);
+                        counts.live -= 1;
+                        waits.changed.notify_all();
+                        report
+                    })
+                })
+                .collect();
+            stages
+                .into_iter()
+                .map(|stage| stage.join().expect("pipeline stage panicked"))
+                .collect::<Vec<_>>()
+        });
+        PipelineReport {
+            signal: stages.last().and_then(|s| s.last_output.clone()),
+            stages,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_series() {
+        let program = IntComputer::try_from("3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0");
+        let program = program.unwrap();
+        let report = Pipeline::series(&program, &[4, 3, 2, 1, 0]).run(0);
+
+        assert_eq!(report.signal, Some(43210));
+        assert!(report.is_complete());
+        assert_eq!(report.stages.len(), 5);
+        assert!(report.stages.iter().all(|s| s.outputs == 1));
+        assert_eq!(report.stages[0].phase, 4);
+        assert_eq!(report.stages[0].last_output, Some(4));
+    }
+
+    #[test]
+    fn test_ring() {
+        let program = IntComputer::try_from(
+            "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5",
+        )
+        .unwrap();
+        let report = Pipeline::ring(&program, &[9, 8, 7, 6, 5]).run(0);
+
+        assert_eq!(report.signal, Some(139629729));
+        assert!(report.is_complete());
+        assert!(report.stages.iter().all(|s| s.outputs == 5));
+    }
+
+    #[test]
+    fn test_failing_stage() {
+        // adds phase and signal, then hits an invalid opcode instead of halting
+        let program = IntComputer::try_from("3,11,3,12,1,11,12,13,4,13,98,0,0,0").unwrap();
+        let report = Pipeline::series(&program, &[1, 2, 3]).run(0);
+
+        assert_eq!(report.signal, Some(6));
+        assert!(!report.is_complete());
+        assert!(report
+            .stages
+            .iter()
+            .all(|s| matches!(s.result, Err(IntcodeError::InvalidOpcode { pc: 10, .. }))));
+    }
+
+    #[test]
+    fn test_deadlock() {
+        // every stage waits for a third input that never comes
+        let program = IntComputer::try_from("3,9,3,9,3,9,4,9,99,0").unwrap();
+        let report = Pipeline::ring(&program, &[1, 2, 3]).run(0);
+
+        assert_eq!(report.signal, None);
+        assert!(!report.is_complete());
+        assert!(report.stages.iter().all(|s| s.deadlocked));
+        assert!(report
+            .stages
+            .iter()
+            .all(|s| s.result == Ok(IntComputerState::AwaitingInput)));
+
+        // a stage left waiting after the others halted is not deadlocked
+        let report = Pipeline::series(&program, &[1]).run(0);
+        assert_eq!(report.stages[0].result, Ok(IntComputerState::AwaitingInput));
+        assert!(!report.stages[0].deadlocked);
+    }
+}
@@ -0,0 +1,197 @@
+//! Execution counts per instruction address and per opcode.
+//!
+//! Timing every instruction would cost more than executing it, so only whole
+//! `run` calls are timed and each hot spot is charged its share of that time
+//! by instruction count. All instructions are assumed to cost the same, which
+//! is close enough to tell which loops dominate.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use super::{InputSource, IntComputer, Memory, Opcode, OutputSink};
+
+/// Executions of one instruction address.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HotSpot {
+    pub pc: usize,
+    /// Opcode last executed at `pc`.
+    pub op: Opcode,
+    pub count: u64,
+    /// Estimated time spent on this instruction.
+    pub time: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    per_pc: HashMap<usize, (Opcode, u64)>,
+    per_op: HashMap<Opcode, u64>,
+    steps: u64,
+    elapsed: Duration,
+}
+
+impl Profile {
+    pub(super) fn record(&mut self, pc: usize, op: Opcode) {
+        let entry = self.per_pc.entry(pc).or_insert((op, 0));
+        *entry = (op, entry.1 + 1);
+        *self.per_op.entry(op).or_insert(0) += 1;
+        self.steps += 1;
+    }
+
+    /// Instructions executed while profiling.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Time spent in `run` and friends while profiling.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn count(&self, pc: usize) -> u64 {
+        self.per_pc.get(&pc).map_or(0, |&(_, count)| count)
+    }
+
+    pub fn op_count(&self, op: Opcode) -> u64 {
+        self.per_op.get(&op).copied().unwrap_or(0)
+    }
+
+    /// Share of the measured time taken by `count` instructions.
+    pub fn estimate(&self, count: u64) -> Duration {
+        if self.steps == 0 {
+            return Duration::ZERO;
+        }
+        self.elapsed.mul_f64(count as f64 / self.steps as f64)
+    }
+
+    /// The `n` most executed addresses, busiest first.
+    pub fn hot_spots(&self, n: usize) -> Vec<HotSpot> {
+        let mut spots: Vec<HotSpot> = self
+            .per_pc
+            .iter()
+            .map(|(&pc, &(op, count))| HotSpot {
+                pc,
+                op,
+                count,
+                time: self.estimate(count),
+            })
+            .collect();
+        spots.sort_by(|a, b| b.count.cmp(&a.count).then(a.pc.cmp(&b.pc)));
+        spots.truncate(n);
+        spots
+    }
+
+    /// Executions per opcode, busiest first.
+    pub fn opcodes(&self) -> Vec<(Opcode, u64)> {
+        let mut ops: Vec<(Opcode, u64)> = self.per_op.iter().map(|(&op, &n)| (op, n)).collect();
+        ops.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.code().cmp(&b.0.code())));
+        ops
+    }
+
+    /// Text report of the `n` hottest addresses followed by all opcodes.
+    pub fn report(&self, n: usize) -> String {
+        let share = |count: u64| 100.0 * count as f64 / self.steps.max(1) as f64;
+        let mut text = String::new();
+        writeln!(text, "{} steps in {:.3?}", self.steps, self.elapsed).unwrap();
+        writeln!(text, "  pc  op        count   share         time").unwrap();
+        for spot in self.hot_spots(n) {
+            writeln!(
+                text,
+                "{:04}  {:<4} {:>10} {:>6.1}% {:>12.3?}",
+                spot.pc,
+                spot.op.mnemonic(),
+                spot.count,
+                share(spot.count),
+                spot.time
+            )
+            .unwrap();
+        }
+        writeln!(text, "op        count   share         time").unwrap();
+        for (op, count) in self.opcodes() {
+            writeln!(
+                text,
+                "{:<4} {:>10} {:>6.1}% {:>12.3?}",
+                op.mnemonic(),
+                count,
+                share(count),
+                self.estimate(count)
+            )
+            .unwrap();
+        }
+        text
+    }
+}
+
+impl<M, I, O> IntComputer<M, I, O>
+where
+    M: Memory,
+    I: InputSource<M::Value>,
+    O: OutputSink<M::Value>,
+{
+    /// Starts counting executed instructions, discarding earlier counts.
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+
+    /// Stops profiling and returns the counts collected.
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Adds the time since `start` to the profile, if profiling.
+    pub(super) fn charge(&mut self, start: Option<Instant>) {
+        if let (Some(profile), Some(start)) = (self.profile.as_mut(), start) {
+            profile.elapsed += start.elapsed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int_code::parse_program;
+
+    #[test]
+    fn test_profile() {
+        // counts [9] down from 100
+        let program = parse_program("1001,9,-1,9,1005,9,0,99,0,100").unwrap();
+        let mut computer = IntComputer::new(program);
+        computer.enable_profiling();
+        computer.run().unwrap();
+        let profile = computer.profile().unwrap();
+
+        assert_eq!(profile.steps(), 201);
+        assert_eq!(profile.count(0), 100);
+        assert_eq!(profile.count(4), 100);
+        assert_eq!(profile.count(7), 1);
+        assert_eq!(profile.op_count(Opcode::JumpTrue), 100);
+        assert_eq!(profile.op_count(Opcode::Stop), 1);
+        assert_eq!(profile.opcodes()[0], (Opcode::Add, 100));
+        assert!(profile.elapsed() > Duration::ZERO);
+
+        let spots = profile.hot_spots(2);
+        assert_eq!(spots.len(), 2);
+        assert_eq!((spots[0].pc, spots[0].op), (0, Opcode::Add));
+        assert_eq!((spots[1].pc, spots[1].op), (4, Opcode::JumpTrue));
+        assert!(spots[0].time <= profile.elapsed());
+    }
+
+    #[test]
+    fn test_report() {
+        let mut computer = IntComputer::new(parse_program("104,1,104,2,99").unwrap());
+        computer.enable_profiling();
+        computer.run().unwrap();
+        let report = computer.take_profile().unwrap().report(1);
+        let lines: Vec<&str> = report.lines().collect();
+
+        assert!(lines[0].starts_with("3 steps in "));
+        assert!(lines[2].starts_with("0000  OUT           1   33.3% "));
+        assert_eq!(lines.len(), 6);
+        assert!(lines[4].starts_with("OUT           2   66.7% "));
+        assert!(computer.profile().is_none());
+    }
+}
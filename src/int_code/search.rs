@@ -0,0 +1,411 @@
+//! Search for the phase settings that give the strongest signal.
+//!
+//! Every ordering of `amplifiers` of the phase values is tried once, chained
+//! in series or in a ring like a `Pipeline`. Worker threads, one per core,
+//! claim the next untried ordering through a shared counter, so a slow
+//! ordering holds up only the worker that claimed it. Orderings are built
+//! from their index, no list of them is kept, so a search is only bounded
+//! by its orderings having to be numbered with a `u64`.
+//!
+//! The amplifiers of one trial take turns on their worker's thread as a
+//! `Network`. A trial ends once no amplifier can move, values left with an
+//! amplifier that already halted do not keep it going. A trial whose program
+//! keeps executing without end holds its worker forever, set `Limits` on the
+//! program to bound it.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+
+use super::network::{Activity, Network, NodeError};
+use super::{IntComputer, Memory};
+
+/// One ordering of phase settings and what it gave.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trial<V> {
+    pub phases: Vec<V>,
+    /// Last output of the last amplifier.
+    pub signal: Option<V>,
+    /// Outputs of each amplifier, in a ring the number of times the signal
+    /// passed through it.
+    pub outputs: Vec<u64>,
+    pub error: Option<NodeError<V>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchReport<V> {
+    /// The trial with the strongest signal, the first ordering wins a tie.
+    pub best: Option<Trial<V>>,
+    pub tried: u64,
+    /// Trials that failed or gave no signal.
+    pub failures: Vec<Trial<V>>,
+}
+
+/// A report with every trial, numbered like the orderings.
+type WithTrials<V> = (SearchReport<V>, Vec<Trial<V>>);
+
+/// The orderings of a search do not fit a `u64`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManyOrderings;
+
+impl fmt::Display for TooManyOrderings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "too many phase orderings to number")
+    }
+}
+
+impl Error for TooManyOrderings {}
+
+/// Search over the orderings of a set of phase settings, see the module
+/// documentation.
+pub struct PhaseSearch<'a, M: Memory> {
+    program: &'a IntComputer<M>,
+    phases: Vec<M::Value>,
+    amplifiers: usize,
+    ring: bool,
+    threads: usize,
+}
+
+impl<'a, M: Memory + Send> PhaseSearch<'a, M> {
+    /// Orders all of `phases` over amplifiers in series.
+    pub fn series(program: &'a IntComputer<M>, phases: &[M::Value]) -> Self {
+        PhaseSearch {
+            program,
+            phases: phases.to_vec(),
+            amplifiers: phases.len(),
+            ring: false,
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+
+    /// Orders all of `phases` over amplifiers in a feedback loop.
+    pub fn ring(program: &'a IntComputer<M>, phases: &[M::Value]) -> Self {
+        PhaseSearch {
+            ring: true,
+            ..PhaseSearch::series(program, phases)
+        }
+    }
+
+    /// Uses only `n` amplifiers, each ordering picks `n` of the phases.
+    pub fn set_amplifiers(&mut self, n: usize) {
+        assert!(
+            n <= self.phases.len(),
+            "{} amplifiers for {} phases",
+            n,
+            self.phases.len()
+        );
+        self.amplifiers = n;
+    }
+
+    /// Worker threads to use, defaults to the number of cores.
+    pub fn set_threads(&mut self, n: usize) {
+        self.threads = n.max(1);
+    }
+
+    /// Number of orderings to try.
+    pub fn count(&self) -> Result<u64, TooManyOrderings> {
+        match self.weights()?.first() {
+            Some(&w) => w
+                .checked_mul(self.phases.len() as u64)
+                .ok_or(TooManyOrderings),
+            None => Ok(1),
+        }
+    }
+
+    /// Tries every ordering with `signal` as the input of the first
+    /// amplifier.
+    pub fn run(&self, signal: M::Value) -> Result<SearchReport<M::Value>, TooManyOrderings> {
+        Ok(self.search(signal, false)?.0)
+    }
+
+    /// Tries every ordering and also returns all trials, in the order the
+    /// orderings are numbered.
+    pub fn trials(&self, signal: M::Value) -> Result<WithTrials<M::Value>, TooManyOrderings> {
+        self.search(signal, true)
+    }
+
+    /// Orderings sharing their first `j + 1` choices number `weights[j]`.
+    fn weights(&self) -> Result<Vec<u64>, TooManyOrderings> {
+        let n = self.phases.len() as u64;
+        let mut weights = vec![1u64; self.amplifiers];
+        for j in (0..self.amplifiers.saturating_sub(1)).rev() {
+            weights[j] = weights[j + 1]
+                .checked_mul(n - j as u64 - 1)
+                .ok_or(TooManyOrderings)?;
+        }
+        Ok(weights)
+    }
+
+    fn search(
+        &self,
+        signal: M::Value,
+        keep: bool,
+    ) -> Result<WithTrials<M::Value>, TooManyOrderings> {
+        let weights = self.weights()?;
+        let count = self.count()?;
+        let next = AtomicU64::new(0);
+        let workers = (self.threads as u64).min(count).max(1);
+
+        let results = thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    // machines and values need not be shareable between threads
+                    let program = self.program.clone();
+                    let phases = self.phases.clone();
+                    let signal = signal.clone();
+                    let (weights, next, ring) = (&weights, &next, self.ring);
+                    scope.spawn(move || {
+                        let mut worker = Worker::default();
+                        loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            if index >= count {
+                                break worker;
+                            }
+                            let order = ordering(&phases, weights, index);
+                            let trial = trial(&program, order, signal.clone(), ring);
+                            worker.record(index, trial, keep);
+                        }
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("search worker panicked"))
+                .collect::<Vec<_>>()
+        });
+
+        let mut best: Option<(u64, Trial<M::Value>)> = None;
+        let mut failures = Vec::new();
+        let mut trials = Vec::new();
+        for worker in results {
+            if let Some((index, trial)) = worker.best {
+                if is_better(&trial, index, &best) {
+                    best = Some((index, trial));
+                }
+            }
+            failures.extend(worker.failures);
+            trials.extend(worker.trials);
+        }
+        failures.sort_by_key(|&(index, _)| index);
+        trials.sort_by_key(|&(index, _)| index);
+        let report = SearchReport {
+            best: best.map(|(_, trial)| trial),
+            tried: count,
+            failures: failures.into_iter().map(|(_, t)| t).collect(),
+        };
+        Ok((report, trials.into_iter().map(|(_, t)| t).collect()))
+    }
+}
+
+/// The ordering numbered `index`, numbered like `Itertools::permutations`.
+fn ordering<V: Clone>(phases: &[V], weights: &[u64], mut index: u64) -> Vec<V> {
+    let mut left = phases.to_vec();
+    weights
+        .iter()
+        .map(|w| {
+            let pick = (index / w) as usize;
+            index %= w;
+            left.remove(pick)
+        })
+        .collect()
+}
+
+/// Runs the amplifiers for one ordering until they stop or stall.
+fn trial<M: Memory>(
+    program: &IntComputer<M>,
+    phases: Vec<M::Value>,
+    signal: M::Value,
+    ring: bool,
+) -> Trial<M::Value> {
+    let mut network = Network::new();
+    for phase in &phases {
+        let amp = network.add(program.clone());
+        network.send(amp, phase.clone());
+    }
+    for amp in 1..phases.len() {
+        network.connect(amp - 1, amp);
+    }
+    let mut trial = Trial {
+        phases,
+        signal: None,
+        outputs: Vec::new(),
+        error: None,
+    };
+    let last = match network.len() {
+        0 => return trial,
+        n => n - 1,
+    };
+    network.send(0, signal);
+    loop {
+        let activity = network.round();
+        // the last amplifier is wired by hand to see what it outputs
+        while let Some(value) = network.node_mut(last).get_output() {
+            if ring {
+                network.send(0, value.clone());
+            }
+            trial.signal = Some(value);
+        }
+        match activity {
+            Ok(Activity::Busy) | Ok(Activity::Woken) => (),
+            Ok(Activity::Idle) | Ok(Activity::Stopped) => break,
+            Err(e) => {
+                trial.error = Some(e);
+                break;
+            }
+        }
+    }
+    trial.outputs = (0..network.len())
+        .map(|amp| network.node(amp).outputs())
+        .collect();
+    trial
+}
+
+/// Whether `trial`, numbered `index`, beats the best one so far.
+fn is_better<V: Ord>(trial: &Trial<V>, index: u64, best: &Option<(u64, Trial<V>)>) -> bool {
+    match best {
+        None => true,
+        Some((best_index, best)) => match trial.signal.cmp(&best.signal) {
+            std::cmp::Ordering::Greater => true,
+            std::cmp::Ordering::Equal => index < *best_index,
+            std::cmp::Ordering::Less => false,
+        },
+    }
+}
+
+/// What one worker thread found, trials carry their ordering number.
+struct Worker<V> {
+    best: Option<(u64, Trial<V>)>,
+    failures: Vec<(u64, Trial<V>)>,
+    trials: Vec<(u64, Trial<V>)>,
+}
+
+impl<V> Default for Worker<V> {
+    fn default() -> Self {
+        Worker {
+            best: None,
+            failures: Vec::new(),
+            trials: Vec::new(),
+        }
+    }
+}
+
+impl<V: Ord + Clone> Worker<V> {
+    fn record(&mut self, index: u64, trial: Trial<V>, keep: bool) {
+        if trial.error.is_some() || trial.signal.is_none() {
+            self.failures.push((index, trial.clone()));
+        } else if is_better(&trial, index, &self.best) {
+            self.best = Some((index, trial.clone()));
+        }
+        if keep {
+            self.trials.push((index, trial));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int_code::IntcodeError;
+    use itertools::Itertools;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_orderings() {
+        let program = IntComputer::new(vec![99]);
+        let phases: Vec<i64> = (0..6).collect();
+        let mut search = PhaseSearch::series(&program, &phases);
+        search.set_amplifiers(3);
+        assert_eq!(search.count(), Ok(120));
+
+        let weights = search.weights().unwrap();
+        let orderings: Vec<_> = (0..120).map(|i| ordering(&phases, &weights, i)).collect();
+        let expected: Vec<_> = phases.iter().copied().permutations(3).collect();
+        assert_eq!(orderings, expected);
+    }
+
+    #[test]
+    fn test_too_many_orderings() {
+        let program = IntComputer::new(vec![99]);
+        let phases: Vec<i64> = (0..21).collect();
+        let mut search = PhaseSearch::series(&program, &phases);
+        assert_eq!(search.count(), Err(TooManyOrderings));
+        assert_eq!(search.run(0), Err(TooManyOrderings));
+
+        search.set_amplifiers(20);
+        assert_eq!(search.count(), Err(TooManyOrderings));
+        // 20! still fits
+        let search = PhaseSearch::series(&program, &phases[..20]);
+        assert_eq!(search.count(), Ok(2_432_902_008_176_640_000));
+    }
+
+    #[test]
+    fn test_series_search() {
+        let program = IntComputer::try_from(
+            "3,23,3,24,1002,24,10,24,1002,23,-1,23,101,5,23,23,1,24,23,23,4,23,99,0,0",
+        )
+        .unwrap();
+        let mut search = PhaseSearch::series(&program, &[0, 1, 2, 3, 4]);
+        search.set_threads(3);
+        let (report, trials) = search.trials(0).unwrap();
+
+        let best = report.best.unwrap();
+        assert_eq!(best.signal, Some(54321));
+        assert_eq!(best.phases, vec![0, 1, 2, 3, 4]);
+        assert_eq!(best.outputs, vec![1; 5]);
+        assert_eq!(report.tried, 120);
+        assert!(report.failures.is_empty());
+        assert_eq!(trials.len(), 120);
+        assert_eq!(trials[0], best);
+    }
+
+    #[test]
+    fn test_ring_search() {
+        let program = IntComputer::try_from(
+            "3,52,1001,52,-5,52,3,53,1,52,56,54,1007,54,5,55,1005,55,26,1001,54,\
+             -5,54,1105,1,12,1,53,54,53,1008,54,0,55,1001,55,1,55,2,53,55,53,4,\
+             53,1001,56,-1,56,1005,56,6,99,0,0,0,0,10",
+        )
+        .unwrap();
+        let report = PhaseSearch::ring(&program, &[5, 6, 7, 8, 9])
+            .run(0)
+            .unwrap();
+
+        let best = report.best.unwrap();
+        assert_eq!(best.signal, Some(18216));
+        assert_eq!(best.phases, vec![9, 7, 8, 5, 6]);
+        assert!(best.outputs.iter().all(|&n| n > 1));
+    }
+
+    #[test]
+    fn test_failures() {
+        // fails unless its phase is 1
+        let program = IntComputer::try_from("3,9,3,10,1005,9,11,98,99,0,0,4,10,99").unwrap();
+        let mut search = PhaseSearch::series(&program, &[0, 1]);
+        search.set_amplifiers(1);
+        let report = search.run(7).unwrap();
+
+        assert_eq!(report.best.unwrap().phases, vec![1]);
+        assert_eq!(report.failures.len(), 1);
+        let error = report.failures[0].error.as_ref().unwrap();
+        assert!(matches!(
+            error.error,
+            IntcodeError::InvalidOpcode { pc: 7, .. }
+        ));
+    }
+
+    #[test]
+    fn test_halt_early() {
+        // halts at once on phase 0, otherwise waits for more input than it gets
+        let program = IntComputer::new(vec![3, 12, 1005, 12, 6, 99, 3, 13, 3, 13, 99, 0, 0, 0]);
+        let report = PhaseSearch::series(&program, &[0, 1]).run(7).unwrap();
+
+        assert_eq!(report.tried, 2);
+        assert!(report.best.is_none());
+        assert_eq!(report.failures.len(), 2);
+        assert!(report
+            .failures
+            .iter()
+            .all(|t| t.signal.is_none() && t.error.is_none()));
+    }
+}
@@ -0,0 +1,170 @@
+//! Detection of self-modifying code.
+//!
+//! A store is reported when it changes a word of an instruction that already
+//! executed, or of one the control flow graph of the program predicts will
+//! execute. Stores that leave the value unchanged are not reported.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use super::cfg::control_flow;
+use super::disasm::Item;
+use super::{InputSource, IntComputer, Memory, OutputSink, Value, ValueType};
+
+/// A store into code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmcEvent<V = ValueType> {
+    /// Number of instructions executed before the storing one.
+    pub step: u64,
+    /// Address of the storing instruction.
+    pub pc: usize,
+    /// Address stored to.
+    pub target: usize,
+    pub old: V,
+    pub new: V,
+    /// Whether `target` was already executed, otherwise it is only predicted
+    /// to be code.
+    pub executed: bool,
+}
+
+impl<V: Value> fmt::Display for SmcEvent<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.executed {
+            "executed"
+        } else {
+            "predicted"
+        };
+        write!(
+            f,
+            "step {} @ {:04} rewrites {} code at {:04}: {} -> {}",
+            self.step, self.pc, kind, self.target, self.old, self.new
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct SmcDetector<V> {
+    executed: HashSet<usize>,
+    predicted: HashSet<usize>,
+    events: Vec<SmcEvent<V>>,
+}
+
+impl<V: Value> SmcDetector<V> {
+    /// Marks the `len` words of the instruction at `pc` as executed.
+    pub(super) fn execute(&mut self, pc: usize, len: usize) {
+        self.executed.extend(pc..pc + len);
+    }
+
+    /// Checks a store of `new` over `old` at `target`.
+    pub(super) fn store(&mut self, step: u64, pc: usize, target: usize, old: V, new: &V) {
+        let executed = self.executed.contains(&target);
+        if old != *new && (executed || self.predicted.contains(&target)) {
+            self.events.push(SmcEvent {
+                step,
+                pc,
+                target,
+                old,
+                new: new.clone(),
+                executed,
+            });
+        }
+    }
+}
+
+impl<M, I, O> IntComputer<M, I, O>
+where
+    M: Memory,
+    I: InputSource<M::Value>,
+    O: OutputSink<M::Value>,
+{
+    /// Starts reporting stores into code. Code is whatever executes from now
+    /// on plus what `control_flow` finds reachable in memory right now.
+    pub fn enable_smc_detection(&mut self) {
+        let program: Vec<ValueType> = match self.mem.segments().into_iter().next() {
+            Some((0, words)) => words.iter().map(|w| w.to_i64().unwrap_or(0)).collect(),
+            _ => Vec::new(),
+        };
+        let mut predicted = HashSet::new();
+        for block in control_flow(&program).blocks.values() {
+            for line in &block.lines {
+                if let Item::Code { .. } = line.item {
+                    predicted.extend(line.addr..line.addr + line.words.len());
+                }
+            }
+        }
+        self.smc = Some(SmcDetector {
+            executed: HashSet::new(),
+            predicted,
+            events: Vec::new(),
+        });
+    }
+
+    pub fn disable_smc_detection(&mut self) {
+        self.smc = None;
+    }
+
+    /// Stores into code seen so far, oldest first.
+    pub fn smc_events(&self) -> &[SmcEvent<M::Value>] {
+        self.smc.as_ref().map_or(&[], |smc| &smc.events[..])
+    }
+
+    /// Returns and forgets the stores into code seen so far.
+    pub fn take_smc_events(&mut self) -> Vec<SmcEvent<M::Value>> {
+        self.smc
+            .as_mut()
+            .map_or_else(Vec::new, |smc| std::mem::take(&mut smc.events))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int_code::parse_program;
+
+    #[test]
+    fn test_rewrite_executed() {
+        // runs OUT #1 twice, turning it into OUT #2 in between
+        let program = parse_program("104,1,1101,0,2,1,1105,1,0").unwrap();
+        let mut computer = IntComputer::new(program);
+        computer.enable_smc_detection();
+        computer.run_steps(4).unwrap();
+
+        assert_eq!(computer.get_output(), Some(1));
+        assert_eq!(computer.get_output(), Some(2));
+        let events = computer.take_smc_events();
+        assert_eq!(
+            events,
+            vec![SmcEvent {
+                step: 1,
+                pc: 2,
+                target: 1,
+                old: 1,
+                new: 2,
+                executed: true
+            }]
+        );
+        assert_eq!(
+            events[0].to_string(),
+            "step 1 @ 0002 rewrites executed code at 0001: 1 -> 2"
+        );
+        // the same store again changes nothing
+        computer.run_steps(3).unwrap();
+        assert!(computer.smc_events().is_empty());
+    }
+
+    #[test]
+    fn test_rewrite_predicted() {
+        // patches the HLT at 8 into an OUT #5 before reaching it
+        let program = parse_program("1101,0,104,8,1101,0,5,9,99,0,99").unwrap();
+        let mut computer = IntComputer::new(program);
+        computer.enable_smc_detection();
+        computer.run().unwrap();
+
+        let events = computer.smc_events();
+        assert_eq!(events.len(), 1);
+        assert_eq!((events[0].target, events[0].executed), (8, false));
+        assert_eq!((events[0].old, events[0].new), (99, 104));
+        // 9 was data when detection started and had not executed yet
+        assert_eq!(computer.get_output(), Some(5));
+    }
+}
@@ -0,0 +1,548 @@
+//! Saving and restoring complete machines.
+//!
+//! Snapshots come in two formats that `Snapshot::load` tells apart on its own:
+//! a compact binary one (magic `ICS1` followed by LEB128 varints, signed
+//! values zigzag encoded) and a line based text one as produced by `Display`:
+//!
+//! ```text
+//! intcode snapshot 1
+//! pc 15
+//! rel_base 0
+//! state Running
+//! yield_on_output false
+//! overflow Saturating
+//! validation Lenient
+//! max_steps 1000
+//! steps 2
+//! outputs 0
+//! input 1,2
+//! output
+//! mem 0 1102,34463338,34463338,63
+//! mem 1000 5,6
+//! ```
+//!
+//! Limits that are not set are left out of the text.
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::{
+    IntComputer, IntComputerState, Limits, Memory, OverflowPolicy, PagedMemory, Queue, Validation,
+    ValueType,
+};
+
+const MAGIC: &[u8] = b"ICS1";
+const HEADER: &str = "intcode snapshot 1";
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    /// Neither the binary magic nor the text header was found.
+    UnknownFormat,
+    /// Binary data ended in the middle of a field.
+    Truncated,
+    /// Binary data went on after the last field.
+    TrailingBytes,
+    /// A field holds something that cannot be restored, with the 1 based
+    /// line for text snapshots.
+    Invalid { line: usize, field: String },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::UnknownFormat => write!(f, "not an intcode snapshot"),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::TrailingBytes => write!(f, "snapshot has trailing bytes"),
+            SnapshotError::Invalid { line, field } => {
+                write!(f, "invalid field {} on line {}", field, line)
+            }
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+/// Everything needed to resume a machine with queue based I/O. Trace sinks
+/// are not part of a snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub pc: usize,
+    pub rel_base: usize,
+    pub state: IntComputerState,
+    pub yield_on_output: bool,
+    pub overflow: OverflowPolicy,
+    pub validation: Validation,
+    pub limits: Limits,
+    pub steps: u64,
+    pub outputs: u64,
+    /// Memory as `(start, values)` blocks, cells outside of them are zero.
+    pub memory: Vec<(usize, Vec<ValueType>)>,
+    pub input: Vec<ValueType>,
+    pub output: Vec<ValueType>,
+}
+
+/// The snapshot of a machine with an empty memory that has not run yet.
+impl Default for Snapshot {
+    fn default() -> Self {
+        Snapshot {
+            pc: 0,
+            rel_base: 0,
+            state: IntComputerState::Initialized,
+            yield_on_output: false,
+            overflow: OverflowPolicy::default(),
+            validation: Validation::default(),
+            limits: Limits::default(),
+            steps: 0,
+            outputs: 0,
+            memory: Vec::new(),
+            input: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+}
+
+impl<M: Memory<Value = ValueType>> IntComputer<M, Queue, Queue> {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pc: self.pc,
+            rel_base: self.rel_base,
+            state: self.state,
+            yield_on_output: self.yield_on_output,
+            overflow: self.overflow,
+            validation: self.validation,
+            limits: self.limits,
+            steps: self.steps,
+            outputs: self.outputs,
+            memory: self.mem.segments(),
+            input: self.input.iter().copied().collect(),
+            output: self.output.iter().copied().collect(),
+        }
+    }
+}
+
+impl IntComputer {
+    /// Rebuilds a computer, a block starting at address 0 becomes the dense
+    /// program segment of its memory.
+    pub fn restore(snapshot: &Snapshot) -> Self {
+        let mut blocks = snapshot.memory.iter().peekable();
+        let program = match blocks.peek() {
+            Some((0, values)) => {
+                blocks.next();
+                values.clone()
+            }
+            _ => Vec::new(),
+        };
+        let mut mem = PagedMemory::new(program);
+        for (start, values) in blocks {
+            for (i, &x) in values.iter().enumerate() {
+                mem.write(start + i, x);
+            }
+        }
+
+        let mut computer = IntComputer::with_memory(mem);
+        computer.pc = snapshot.pc;
+        computer.rel_base = snapshot.rel_base;
+        computer.state = snapshot.state;
+        computer.yield_on_output = snapshot.yield_on_output;
+        computer.overflow = snapshot.overflow;
+        computer.validation = snapshot.validation;
+        computer.limits = snapshot.limits;
+        computer.steps = snapshot.steps;
+        computer.outputs = snapshot.outputs;
+        computer.input.extend(&snapshot.input);
+        computer.output.extend(&snapshot.output);
+        computer
+    }
+}
+
+const STATES: [IntComputerState; 5] = [
+    IntComputerState::Initialized,
+    IntComputerState::Running,
+    IntComputerState::AwaitingInput,
+    IntComputerState::OutputReady,
+    IntComputerState::Stopped,
+];
+
+const POLICIES: [OverflowPolicy; 4] = [
+    OverflowPolicy::Wrapping,
+    OverflowPolicy::Saturating,
+    OverflowPolicy::Checked,
+    OverflowPolicy::Promote,
+];
+
+const VALIDATIONS: [Validation; 2] = [Validation::Lenient, Validation::Strict];
+
+fn put_varint(buf: &mut Vec<u8>, mut x: u64) {
+    while x >= 0x80 {
+        buf.push(x as u8 | 0x80);
+        x >>= 7;
+    }
+    buf.push(x as u8);
+}
+
+fn put_value(buf: &mut Vec<u8>, x: ValueType) {
+    put_varint(buf, ((x << 1) ^ (x >> 63)) as u64);
+}
+
+fn put_values(buf: &mut Vec<u8>, values: &[ValueType]) {
+    put_varint(buf, values.len() as u64);
+    values.iter().for_each(|&x| put_value(buf, x));
+}
+
+/// A flag followed by the value if there is one.
+fn put_limit(buf: &mut Vec<u8>, limit: Option<u64>) {
+    match limit {
+        Some(x) => {
+            put_varint(buf, 1);
+            put_varint(buf, x);
+        }
+        None => put_varint(buf, 0),
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn varint(&mut self) -> Result<u64, SnapshotError> {
+        let mut x = 0;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self.0.split_first().ok_or(SnapshotError::Truncated)?;
+            self.0 = rest;
+            x |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(x);
+            }
+        }
+        Err(SnapshotError::Invalid {
+            line: 0,
+            field: "varint".to_string(),
+        })
+    }
+
+    fn value(&mut self) -> Result<ValueType, SnapshotError> {
+        let x = self.varint()?;
+        Ok((x >> 1) as ValueType ^ -((x & 1) as ValueType))
+    }
+
+    fn values(&mut self) -> Result<Vec<ValueType>, SnapshotError> {
+        let len = self.varint()?;
+        (0..len).map(|_| self.value()).collect()
+    }
+
+    fn limit(&mut self) -> Result<Option<u64>, SnapshotError> {
+        match self.varint()? {
+            0 => Ok(None),
+            _ => Ok(Some(self.varint()?)),
+        }
+    }
+
+    /// The entry of `table` numbered by the next varint.
+    fn pick<T: Copy>(&mut self, table: &[T], field: &str) -> Result<T, SnapshotError> {
+        let index = self.varint()? as usize;
+        table
+            .get(index)
+            .copied()
+            .ok_or_else(|| SnapshotError::Invalid {
+                line: 0,
+                field: field.to_string(),
+            })
+    }
+}
+
+fn nanos(time: Duration) -> u64 {
+    time.as_nanos().min(u64::MAX as u128) as u64
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        put_varint(&mut buf, self.pc as u64);
+        put_varint(&mut buf, self.rel_base as u64);
+        let state = STATES.iter().position(|&s| s == self.state).unwrap();
+        put_varint(&mut buf, state as u64);
+        put_varint(&mut buf, self.yield_on_output as u64);
+        put_varint(&mut buf, self.steps);
+        put_varint(&mut buf, self.memory.len() as u64);
+        for (start, values) in self.memory.iter() {
+            put_varint(&mut buf, *start as u64);
+            put_values(&mut buf, values);
+        }
+        put_values(&mut buf, &self.input);
+        put_values(&mut buf, &self.output);
+        let policy = POLICIES.iter().position(|&p| p == self.overflow).unwrap();
+        put_varint(&mut buf, policy as u64);
+        let validation = VALIDATIONS.iter().position(|&v| v == self.validation);
+        put_varint(&mut buf, validation.unwrap() as u64);
+        let limits = &self.limits;
+        put_limit(&mut buf, limits.max_steps);
+        put_limit(&mut buf, limits.max_memory.map(|x| x as u64));
+        put_limit(&mut buf, limits.max_time.map(nanos));
+        put_limit(&mut buf, limits.max_output);
+        put_varint(&mut buf, self.outputs);
+        buf
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Snapshot, SnapshotError> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(SnapshotError::UnknownFormat);
+        }
+        let mut r = Reader(&bytes[MAGIC.len()..]);
+        let pc = r.varint()? as usize;
+        let rel_base = r.varint()? as usize;
+        let state = r.pick(&STATES, "state")?;
+        let yield_on_output = r.varint()? != 0;
+        let steps = r.varint()?;
+        let blocks = r.varint()?;
+        let memory = (0..blocks)
+            .map(|_| Ok((r.varint()? as usize, r.values()?)))
+            .collect::<Result<_, SnapshotError>>()?;
+        let input = r.values()?;
+        let output = r.values()?;
+        let overflow = r.pick(&POLICIES, "overflow")?;
+        let validation = r.pick(&VALIDATIONS, "validation")?;
+        let limits = Limits {
+            max_steps: r.limit()?,
+            max_memory: r.limit()?.map(|x| x as usize),
+            max_time: r.limit()?.map(Duration::from_nanos),
+            max_output: r.limit()?,
+        };
+        let outputs = r.varint()?;
+        if !r.0.is_empty() {
+            return Err(SnapshotError::TrailingBytes);
+        }
+        Ok(Snapshot {
+            pc,
+            rel_base,
+            state,
+            yield_on_output,
+            overflow,
+            validation,
+            limits,
+            steps,
+            outputs,
+            memory,
+            input,
+            output,
+        })
+    }
+
+    /// Writes the binary format.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SnapshotError> {
+        Ok(fs::write(path, self.to_bytes())?)
+    }
+
+    /// Reads a snapshot in either format.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot, SnapshotError> {
+        let bytes = fs::read(path)?;
+        if bytes.starts_with(MAGIC) {
+            return Snapshot::from_bytes(&bytes);
+        }
+        match String::from_utf8(bytes) {
+            Ok(text) => text.parse(),
+            Err(_) => Err(SnapshotError::UnknownFormat),
+        }
+    }
+}
+
+fn join(values: &[ValueType]) -> String {
+    let values: Vec<String> = values.iter().map(|x| x.to_string()).collect();
+    values.join(",")
+}
+
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+        writeln!(f, "pc {}", self.pc)?;
+        writeln!(f, "rel_base {}", self.rel_base)?;
+        writeln!(f, "state {:?}", self.state)?;
+        writeln!(f, "yield_on_output {}", self.yield_on_output)?;
+        writeln!(f, "overflow {:?}", self.overflow)?;
+        writeln!(f, "validation {:?}", self.validation)?;
+        let limits = &self.limits;
+        if let Some(max) = limits.max_steps {
+            writeln!(f, "max_steps {}", max)?;
+        }
+        if let Some(max) = limits.max_memory {
+            writeln!(f, "max_memory {}", max)?;
+        }
+        if let Some(max) = limits.max_time {
+            writeln!(f, "max_time_ns {}", nanos(max))?;
+        }
+        if let Some(max) = limits.max_output {
+            writeln!(f, "max_output {}", max)?;
+        }
+        writeln!(f, "steps {}", self.steps)?;
+        writeln!(f, "outputs {}", self.outputs)?;
+        writeln!(f, "input {}", join(&self.input))?;
+        writeln!(f, "output {}", join(&self.output))?;
+        for (start, values) in self.memory.iter() {
+            writeln!(f, "mem {} {}", start, join(values))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Snapshot {
+    type Err = SnapshotError;
+
+    fn from_str(text: &str) -> Result<Snapshot, SnapshotError> {
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, header)) if header.trim() == HEADER => (),
+            _ => return Err(SnapshotError::UnknownFormat),
+        }
+
+        let mut snapshot = Snapshot::default();
+        for (n, line) in lines {
+            let invalid = |field: &str| SnapshotError::Invalid {
+                line: n + 1,
+                field: field.to_string(),
+            };
+            let mut parts = line.split_whitespace();
+            let (field, value) = match (parts.next(), parts.next()) {
+                (Some(field), value) => (field, value.unwrap_or("")),
+                (None, _) => continue,
+            };
+            let list = |s: &str| -> Result<Vec<ValueType>, SnapshotError> {
+                if s.is_empty() {
+                    return Ok(Vec::new());
+                }
+                s.split(',').map(|x| x.parse().map_err(|_| invalid(field))).collect()
+            };
+            match field {
+                "pc" => snapshot.pc = value.parse().map_err(|_| invalid(field))?,
+                "rel_base" => snapshot.rel_base = value.parse().map_err(|_| invalid(field))?,
+                "steps" => snapshot.steps = value.parse().map_err(|_| invalid(field))?,
+                "outputs" => snapshot.outputs = value.parse().map_err(|_| invalid(field))?,
+                "max_steps" => {
+                    snapshot.limits.max_steps = Some(value.parse().map_err(|_| invalid(field))?)
+                }
+                "max_memory" => {
+                    snapshot.limits.max_memory = Some(value.parse().map_err(|_| invalid(field))?)
+                }
+                "max_time_ns" => {
+                    let nanos = value.parse().map_err(|_| invalid(field))?;
+                    snapshot.limits.max_time = Some(Duration::from_nanos(nanos))
+                }
+                "max_output" => {
+                    snapshot.limits.max_output = Some(value.parse().map_err(|_| invalid(field))?)
+                }
+                "overflow" => {
+                    snapshot.overflow = *POLICIES
+                        .iter()
+                        .find(|p| format!("{:?}", p) == value)
+                        .ok_or_else(|| invalid(field))?
+                }
+                "validation" => {
+                    snapshot.validation = *VALIDATIONS
+                        .iter()
+                        .find(|v| format!("{:?}", v) == value)
+                        .ok_or_else(|| invalid(field))?
+                }
+                "yield_on_output" => {
+                    snapshot.yield_on_output = value.parse().map_err(|_| invalid(field))?
+                }
+                "state" => {
+                    snapshot.state = *STATES
+                        .iter()
+                        .find(|s| format!("{:?}", s) == value)
+                        .ok_or_else(|| invalid(field))?
+                }
+                "input" => snapshot.input = list(value)?,
+                "output" => snapshot.output = list(value)?,
+                "mem" => {
+                    let start = value.parse().map_err(|_| invalid(field))?;
+                    snapshot.memory.push((start, list(parts.next().unwrap_or(""))?));
+                }
+                _ => return Err(invalid(field)),
+            }
+        }
+        Ok(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn paused() -> IntComputer {
+        // doubles its inputs, storing the running total far out in memory
+        let mut computer =
+            IntComputer::try_from("3,20,1002,20,2,20,1,20,5000,5000,4,5000,1105,1,0").unwrap();
+        computer.push_input(21);
+        computer.push_input(-4);
+        computer.set_yield_on_output(true);
+        computer.set_overflow_policy(OverflowPolicy::Checked);
+        computer.set_validation(Validation::Strict);
+        computer.set_limits(Limits {
+            max_steps: Some(1000),
+            max_time: Some(Duration::from_millis(1500)),
+            ..Limits::default()
+        });
+        computer.run().unwrap();
+        computer
+    }
+
+    #[test]
+    fn test_binary_round_trip() {
+        let computer = paused();
+        let snapshot = computer.snapshot();
+        assert_eq!(snapshot.memory[1..], [(20, vec![42]), (5000, vec![42])]);
+
+        let bytes = snapshot.to_bytes();
+        let restored = Snapshot::from_bytes(&bytes).unwrap();
+        assert_eq!(restored, snapshot);
+        assert!(matches!(
+            Snapshot::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SnapshotError::Truncated)
+        ));
+        let mut padded = bytes.clone();
+        padded.push(0);
+        assert!(matches!(
+            Snapshot::from_bytes(&padded),
+            Err(SnapshotError::TrailingBytes)
+        ));
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let snapshot = paused().snapshot();
+
+        let text = snapshot.to_string();
+        assert!(text.contains("\nstate OutputReady\n"));
+        assert!(text.contains("\ninput -4\noutput 42\n"));
+        assert!(text.contains("\noverflow Checked\nvalidation Strict\nmax_steps 1000\n"));
+        assert!(text.contains("\nmax_time_ns 1500000000\nsteps 4\noutputs 1\n"));
+        assert_eq!(text.parse::<Snapshot>().unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_restore_resumes() {
+        let mut original = paused();
+        let mut restored = IntComputer::restore(&original.snapshot());
+        assert_eq!(restored.overflow_policy(), OverflowPolicy::Checked);
+        assert_eq!(restored.validation(), Validation::Strict);
+        assert_eq!(restored.limits(), original.limits());
+        assert_eq!(restored.outputs(), 1);
+
+        for computer in [&mut original, &mut restored].iter_mut() {
+            assert_eq!(computer.get_output(), Some(42));
+            assert_eq!(computer.run(), Ok(IntComputerState::OutputReady));
+            assert_eq!(computer.get_output(), Some(34));
+        }
+        assert_eq!(original.snapshot(), restored.snapshot());
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use super::{Opcode, Value, ValueType};
+
+/// One executed instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent<V = ValueType> {
+    /// Number of instructions executed before this one.
+    pub step: u64,
+    pub pc: usize,
+    pub op: Opcode,
+    pub word: V,
+    /// Values of the operands that were read, in parameter order.
+    pub reads: Vec<V>,
+    /// Address and value stored by the instruction.
+    pub write: Option<(usize, V)>,
+    /// Relative base at the time the instruction executed.
+    pub rel_base: usize,
+}
+
+impl<V: Value> TraceEvent<V> {
+    /// The event as a single line JSON object.
+    pub fn to_json(&self) -> String {
+        let reads: Vec<String> = self.reads.iter().map(|x| x.to_string()).collect();
+        let write = match &self.write {
+            Some((addr, value)) => format!("[{},{}]", addr, value),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"step\":{},\"pc\":{},\"op\":\"{}\",\"word\":{},\"reads\":[{}],\"write\":{},\"rel_base\":{}}}",
+            self.step,
+            self.pc,
+            self.op.mnemonic(),
+            self.word,
+            reads.join(","),
+            write,
+            self.rel_base
+        )
+    }
+}
+
+impl<V: Value> fmt::Display for TraceEvent<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>8} {:04} {:<4} rb {:<6} {:?}",
+            self.step,
+            self.pc,
+            self.op.mnemonic(),
+            self.rel_base,
+            self.reads
+        )?;
+        if let Some((addr, value)) = &self.write {
+            write!(f, " -> [{}] = {}", addr, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Receives the events of a traced `IntComputer`.
+pub trait TraceSink<V = ValueType> {
+    fn record(&mut self, event: &TraceEvent<V>);
+}
+
+/// Keeps the last `capacity` events. Clones share the same buffer, so a
+/// handle kept outside the machine can read what was recorded.
+#[derive(Debug, Clone)]
+pub struct TraceRing<V = ValueType> {
+    capacity: usize,
+    events: Arc<Mutex<VecDeque<TraceEvent<V>>>>,
+}
+
+impl<V: Value> TraceRing<V> {
+    pub fn new(capacity: usize) -> Self {
+        TraceRing {
+            capacity,
+            events: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+        }
+    }
+
+    /// Recorded events, oldest first.
+    pub fn events(&self) -> Vec<TraceEvent<V>> {
+        self.events.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+impl<V: Value> TraceSink<V> for TraceRing<V> {
+    fn record(&mut self, event: &TraceEvent<V>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut events = self.events.lock().unwrap();
+        if events.len() == self.capacity {
+            events.pop_front();
+        }
+        events.push_back(event.clone());
+    }
+}
+
+/// Writes one JSON object per line, e.g. into a file for diffing runs.
+pub struct JsonLines<W>(pub W);
+
+impl<W: Write, V: Value> TraceSink<V> for JsonLines<W> {
+    fn record(&mut self, event: &TraceEvent<V>) {
+        let _ = writeln!(self.0, "{}", event.to_json());
+    }
+}
+
+/// Human readable lines, `TextTrace(std::io::stderr())` traces to the terminal.
+pub struct TextTrace<W>(pub W);
+
+impl<W: Write, V: Value> TraceSink<V> for TextTrace<W> {
+    fn record(&mut self, event: &TraceEvent<V>) {
+        let _ = writeln!(self.0, "{}", event);
+    }
+}
+
+/// Index of the first position where two traces differ, `None` if one is a
+/// prefix of the other.
+pub fn first_divergence<V: PartialEq>(a: &[TraceEvent<V>], b: &[TraceEvent<V>]) -> Option<usize> {
+    a.iter().zip(b).position(|(x, y)| x != y)
+}
+
+/// Optional sink held by the computer. Sinks are not shared between clones.
+pub(super) struct Tracer<V>(Option<Box<dyn TraceSink<V> + Send>>);
+
+impl<V> Tracer<V> {
+    pub(super) fn new<T: TraceSink<V> + Send + 'static>(sink: T) -> Self {
+        Tracer(Some(Box::new(sink)))
+    }
+
+    #[inline]
+    pub(super) fn sink(&mut self) -> Option<&mut (dyn TraceSink<V> + Send + 'static)> {
+        self.0.as_deref_mut()
+    }
+
+    pub(super) fn take(&mut self) -> Option<Box<dyn TraceSink<V> + Send>> {
+        self.0.take()
+    }
+}
+
+impl<V> Default for Tracer<V> {
+    fn default() -> Self {
+        Tracer(None)
+    }
+}
+
+impl<V> Clone for Tracer<V> {
+    fn clone(&self) -> Self {
+        Tracer(None)
+    }
+}
+
+impl<V> fmt::Debug for Tracer<V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(if self.0.is_some() { "Tracer(on)" } else { "Tracer(off)" })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int_code::IntComputer;
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_trace_ring() {
+        let mut program = IntComputer::try_from("3,9,1001,9,5,9,4,9,99,0").unwrap();
+        let ring = TraceRing::new(3);
+        program.set_tracer(ring.clone());
+        program.push_input(2);
+        program.run().unwrap();
+
+        let events = ring.events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(
+            events[0],
+            TraceEvent {
+                step: 1,
+                pc: 2,
+                op: Opcode::Add,
+                word: 1001,
+                reads: vec![2, 5],
+                write: Some((9, 7)),
+                rel_base: 0,
+            }
+        );
+        assert_eq!(events[1].reads, vec![7]);
+        assert_eq!(events[2].op, Opcode::Stop);
+        assert_eq!(program.steps(), 4);
+
+        let mut program = IntComputer::try_from("104,1,99").unwrap();
+        let ring = TraceRing::new(0);
+        program.set_tracer(ring.clone());
+        program.run().unwrap();
+        assert!(ring.events().is_empty());
+    }
+
+    #[test]
+    fn test_json_event() {
+        let mut program = IntComputer::try_from("109,4,204,-2,99").unwrap();
+        let ring = TraceRing::new(10);
+        program.set_tracer(ring.clone());
+        program.run().unwrap();
+        assert_eq!(
+            ring.events()[1].to_json(),
+            r#"{"step":1,"pc":2,"op":"OUT","word":204,"reads":[204],"write":null,"rel_base":4}"#
+        );
+    }
+
+    #[test]
+    fn test_first_divergence() {
+        let run = |input| {
+            let mut program = IntComputer::try_from("3,8,1006,8,7,104,1,99,0").unwrap();
+            let ring = TraceRing::new(100);
+            program.set_tracer(ring.clone());
+            program.push_input(input);
+            program.run().unwrap();
+            ring.events()
+        };
+        assert_eq!(first_divergence(&run(1), &run(1)), None);
+        assert_eq!(first_divergence(&run(0), &run(1)), Some(0));
+    }
+}
@@ -0,0 +1,16 @@
+/// How closely a machine holds programs to the specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Validation {
+    /// Accepts what the interpreter has always accepted: a write parameter in
+    /// immediate mode stores into the parameter itself, overwriting the
+    /// instruction stream. Negative addresses and jump targets are cast to
+    /// `usize` as they always were and fail with `IntcodeError::OutOfBounds`
+    /// at the address they wrap to.
+    #[default]
+    Lenient,
+    /// Write parameters in immediate mode fail with
+    /// `IntcodeError::ImmediateWrite`, negative operand addresses with
+    /// `IntcodeError::NegativeAddress` and jumps to negative targets with
+    /// `IntcodeError::NegativeJump`.
+    Strict,
+}
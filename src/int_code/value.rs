@@ -0,0 +1,285 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::hash::Hash;
+use std::str::FromStr;
+
+use super::{
+    IntComputer, IntComputerState, IntcodeError, Memory, OverflowPolicy, PagedMemory, Queue,
+    ValueType,
+};
+
+/// Numeric type a machine computes with.
+///
+/// Instruction words, addresses and jump targets always have to fit an
+/// `i64`, only the data a program works on benefits from a wider type.
+pub trait Value:
+    Clone + Default + Ord + Hash + fmt::Debug + fmt::Display + FromStr + From<i64> + Send + 'static
+{
+    /// The value as an `i64`, `None` if it does not fit.
+    fn to_i64(&self) -> Option<i64>;
+
+    /// `self + rhs`, `None` if `policy` rejects the result.
+    fn add_with(&self, rhs: &Self, policy: OverflowPolicy) -> Option<Self>;
+
+    /// `self * rhs`, `None` if `policy` rejects the result.
+    fn mul_with(&self, rhs: &Self, policy: OverflowPolicy) -> Option<Self>;
+
+    fn is_zero(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+macro_rules! impl_value {
+    ($($t:ty),*) => {$(
+        impl Value for $t {
+            #[inline]
+            fn to_i64(&self) -> Option<i64> {
+                i64::try_from(*self).ok()
+            }
+
+            #[inline]
+            fn add_with(&self, rhs: &Self, policy: OverflowPolicy) -> Option<Self> {
+                match policy {
+                    OverflowPolicy::Wrapping => Some(self.wrapping_add(*rhs)),
+                    OverflowPolicy::Saturating => Some(self.saturating_add(*rhs)),
+                    OverflowPolicy::Checked | OverflowPolicy::Promote => self.checked_add(*rhs),
+                }
+            }
+
+            #[inline]
+            fn mul_with(&self, rhs: &Self, policy: OverflowPolicy) -> Option<Self> {
+                match policy {
+                    OverflowPolicy::Wrapping => Some(self.wrapping_mul(*rhs)),
+                    OverflowPolicy::Saturating => Some(self.saturating_mul(*rhs)),
+                    OverflowPolicy::Checked | OverflowPolicy::Promote => self.checked_mul(*rhs),
+                }
+            }
+
+            #[inline]
+            fn is_zero(&self) -> bool {
+                *self == 0
+            }
+        }
+    )*};
+}
+
+impl_value!(i64, i128);
+
+/// Exact arithmetic, the overflow policy never comes into play.
+#[cfg(feature = "bigint")]
+impl Value for num_bigint::BigInt {
+    fn to_i64(&self) -> Option<i64> {
+        i64::try_from(self).ok()
+    }
+
+    fn add_with(&self, rhs: &Self, _: OverflowPolicy) -> Option<Self> {
+        Some(self + rhs)
+    }
+
+    fn mul_with(&self, rhs: &Self, _: OverflowPolicy) -> Option<Self> {
+        Some(self * rhs)
+    }
+}
+
+impl<M: Memory> IntComputer<M, Queue<M::Value>, Queue<M::Value>> {
+    /// Moves the machine to the wider value type `W`, keeping memory,
+    /// registers, settings and queued I/O. Tracer, history, coverage,
+    /// profile, self-modification events and custom instructions stay behind.
+    pub fn widen<W: Value + From<M::Value>>(self) -> IntComputer<PagedMemory<W>> {
+        let mut segments = self.mem.segments().into_iter().peekable();
+        let program = match segments.peek() {
+            Some((0, _)) => segments.next().unwrap().1,
+            _ => Vec::new(),
+        };
+        let mut mem = PagedMemory::new(program.into_iter().map(W::from).collect());
+        for (start, values) in segments {
+            for (i, x) in values.into_iter().enumerate() {
+                mem.write(start + i, W::from(x));
+            }
+        }
+
+        let mut wide = IntComputer::with_memory(mem);
+        wide.pc = self.pc;
+        wide.rel_base = self.rel_base;
+        wide.state = self.state;
+        wide.yield_on_output = self.yield_on_output;
+        wide.overflow = self.overflow;
+        wide.validation = self.validation;
+        wide.limits = self.limits;
+        wide.steps = self.steps;
+        wide.outputs = self.outputs;
+        wide.input = self.input.into_iter().map(W::from).collect();
+        wide.output = self.output.into_iter().map(W::from).collect();
+        wide
+    }
+}
+
+/// The widest value type built in, a `Promoting` machine reports its values
+/// in it.
+#[cfg(feature = "bigint")]
+pub type WideValue = num_bigint::BigInt;
+#[cfg(not(feature = "bigint"))]
+pub type WideValue = i128;
+
+/// A machine that starts out computing with `i64` and moves to `i128`, and
+/// with the `bigint` feature on to `BigInt`, whenever a result does not fit
+/// under `OverflowPolicy::Promote`. With any other policy it never widens.
+#[derive(Debug, Clone)]
+pub enum Promoting {
+    I64(IntComputer),
+    I128(IntComputer<PagedMemory<i128>>),
+    #[cfg(feature = "bigint")]
+    Big(IntComputer<PagedMemory<num_bigint::BigInt>>),
+}
+
+impl Promoting {
+    /// Loads `program` with the `Promote` overflow policy.
+    pub fn new(program: Vec<ValueType>) -> Self {
+        let mut computer = IntComputer::new(program);
+        computer.set_overflow_policy(OverflowPolicy::Promote);
+        Promoting::I64(computer)
+    }
+
+    pub fn push_input(&mut self, value: ValueType) {
+        match self {
+            Promoting::I64(c) => c.push_input(value),
+            Promoting::I128(c) => c.push_input(value.into()),
+            #[cfg(feature = "bigint")]
+            Promoting::Big(c) => c.push_input(value.into()),
+        }
+    }
+
+    // without `bigint` the i128 arm converts to itself
+    #[allow(clippy::useless_conversion)]
+    pub fn get_output(&mut self) -> Option<WideValue> {
+        match self {
+            Promoting::I64(c) => c.get_output().map(WideValue::from),
+            Promoting::I128(c) => c.get_output().map(WideValue::from),
+            #[cfg(feature = "bigint")]
+            Promoting::Big(c) => c.get_output(),
+        }
+    }
+
+    /// Runs like `IntComputer::run`, widening and resuming the faulting
+    /// instruction whenever a result overflows.
+    pub fn run(&mut self) -> Result<IntComputerState, IntcodeError<WideValue>> {
+        loop {
+            let result = match self {
+                Promoting::I64(c) => c.run().map_err(IntcodeError::convert),
+                Promoting::I128(c) => c.run().map_err(IntcodeError::convert),
+                #[cfg(feature = "bigint")]
+                Promoting::Big(c) => c.run(),
+            };
+            match result {
+                Err(IntcodeError::Overflow { .. }) if self.promote() => (),
+                result => return result,
+            }
+        }
+    }
+
+    /// Moves to the next wider type, returns false if there is none or the
+    /// policy does not ask for it.
+    fn promote(&mut self) -> bool {
+        // a cheap stand in while the machine is moved
+        let placeholder = Promoting::I64(IntComputer::new(Vec::new()));
+        *self = match std::mem::replace(self, placeholder) {
+            Promoting::I64(c) if c.overflow_policy() == OverflowPolicy::Promote => {
+                Promoting::I128(c.widen())
+            }
+            #[cfg(feature = "bigint")]
+            Promoting::I128(c) if c.overflow_policy() == OverflowPolicy::Promote => {
+                Promoting::Big(c.widen())
+            }
+            other => {
+                *self = other;
+                return false;
+            }
+        };
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::int_code::{parse_program, IntcodeError};
+
+    // squares the input and outputs the result
+    const SQUARE: &str = "3,11,2,11,11,11,4,11,99";
+
+    #[test]
+    fn test_i128_machine() {
+        let program = parse_program::<i128>(SQUARE).unwrap();
+        let mut computer = IntComputer::with_memory(PagedMemory::new(program));
+        computer.set_overflow_policy(OverflowPolicy::Checked);
+        computer.push_input(1 << 40);
+        computer.run().unwrap();
+
+        assert_eq!(computer.get_output(), Some(1 << 80));
+    }
+
+    #[test]
+    fn test_widen_after_overflow() {
+        let mut computer = IntComputer::new(parse_program(SQUARE).unwrap());
+        computer.set_overflow_policy(OverflowPolicy::Checked);
+        computer.push_input(1 << 40);
+        let err = computer.run().unwrap_err();
+        assert!(matches!(err, IntcodeError::Overflow { pc: 2, .. }));
+
+        let mut computer = computer.widen::<i128>();
+        computer.run().unwrap();
+        assert_eq!(computer.get_output(), Some(1 << 80));
+        assert_eq!(computer.steps(), 4);
+    }
+
+    #[test]
+    fn test_promoting() {
+        // squares the input twice
+        let program = parse_program("3,13,2,13,13,13,2,13,13,13,4,13,99,0").unwrap();
+        let mut computer = Promoting::new(program.clone());
+        computer.push_input(3);
+        assert_eq!(computer.run(), Ok(IntComputerState::Stopped));
+        assert_eq!(computer.get_output(), Some(WideValue::from(81)));
+        assert!(matches!(computer, Promoting::I64(_)));
+
+        let mut computer = Promoting::new(program.clone());
+        computer.push_input(1 << 20);
+        computer.run().unwrap();
+        assert_eq!(computer.get_output(), Some(WideValue::from(1i128 << 80)));
+        assert!(matches!(computer, Promoting::I128(_)));
+
+        let mut computer = Promoting::new(program);
+        computer.push_input(1 << 40);
+        let result = computer.run();
+        #[cfg(not(feature = "bigint"))]
+        assert!(matches!(result, Err(IntcodeError::Overflow { pc: 6, .. })));
+        #[cfg(feature = "bigint")]
+        {
+            assert_eq!(result, Ok(IntComputerState::Stopped));
+            let expected = num_bigint::BigInt::from(1) << 160;
+            assert_eq!(computer.get_output(), Some(expected));
+            assert!(matches!(computer, Promoting::Big(_)));
+        }
+
+        // other policies never widen
+        let mut computer = Promoting::new(parse_program(SQUARE).unwrap());
+        if let Promoting::I64(c) = &mut computer {
+            c.set_overflow_policy(OverflowPolicy::Checked);
+        }
+        computer.push_input(1 << 40);
+        assert!(matches!(computer.run(), Err(IntcodeError::Overflow { pc: 2, .. })));
+    }
+
+    #[cfg(feature = "bigint")]
+    #[test]
+    fn test_bigint_machine() {
+        use num_bigint::BigInt;
+
+        let program = parse_program::<BigInt>(SQUARE).unwrap();
+        let mut computer = IntComputer::with_memory(PagedMemory::new(program));
+        computer.push_input(BigInt::from(1) << 100);
+        computer.run().unwrap();
+
+        assert_eq!(computer.get_output(), Some(BigInt::from(1) << 200));
+    }
+}
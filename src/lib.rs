@@ -0,0 +1,3 @@
+pub mod day_7;
+pub mod day_9;
+pub mod int_code;
@@ -94,10 +94,18 @@ This time, when the TEST diagnostic program runs its input instruction to get th
 
 What is the diagnostic code for system ID 5?
 */
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process;
 use std::time::{Duration, Instant};
 
-mod int_code;
-mod day_9;
+use day_2::day_9;
+use day_2::int_code::debugger::Debugger;
+use day_2::int_code::trace::JsonLines;
+use day_2::int_code::{
+    control_flow, disassemble, parse_program, IntComputer, Memory, PagedMemory, ValueType,
+};
 
 #[inline(always)]
 fn timed<T>(f: impl FnOnce() -> T) -> (T, Duration) {
@@ -107,11 +115,157 @@ fn timed<T>(f: impl FnOnce() -> T) -> (T, Duration) {
     (result, end - start)
 }
 
+fn load_program(path: &str) -> Vec<ValueType> {
+    let text = fs::read_to_string(path).expect("File not found");
+    parse_program(&text).expect("Failed to parse IntCode Program")
+}
+
+fn usage() -> ! {
+    eprintln!("usage: day_2 [disasm|cfg|debug [FILE] | trace|cover|profile [FILE [INPUT...]] | bench [FILE [RUNS]]]");
+    process::exit(1);
+}
+
+/// Runs the program fed with `input` once `enable` has set the machine up,
+/// then hands the machine to `report`. A run that fails is reported too.
+fn instrumented(
+    program: Vec<ValueType>,
+    input: &[String],
+    enable: impl FnOnce(&mut IntComputer),
+    report: impl FnOnce(IntComputer),
+) {
+    let mut computer = IntComputer::new(program);
+    for x in input {
+        computer.push_input(x.parse().unwrap_or_else(|_| usage()));
+    }
+    enable(&mut computer);
+    if let Err(e) = computer.run() {
+        eprintln!("{}", e);
+    }
+    report(computer);
+}
+
+/// Runs the program writing one JSON line per executed instruction to stdout.
+fn trace(program: Vec<ValueType>, input: &[String]) {
+    instrumented(
+        program,
+        input,
+        |c| c.set_tracer(JsonLines(io::BufWriter::new(io::stdout()))),
+        drop,
+    );
+}
+
+/// Best of `runs` complete runs of `machine` fed with `input`.
+fn bench_case<M: Memory>(name: &str, machine: &IntComputer<M>, input: M::Value, runs: usize) {
+    let mut best = Duration::from_secs(u64::MAX);
+    let mut steps = 0;
+    for _ in 0..runs {
+        let mut computer = machine.clone();
+        computer.push_input(input.clone());
+        let (result, dur) = timed(|| computer.run());
+        if let Err(e) = result {
+            eprintln!("{}: {}", name, e);
+            return;
+        }
+        steps = computer.steps();
+        best = best.min(dur);
+    }
+    let rate = steps as f64 / best.as_secs_f64() / 1e6;
+    println!("{:<8} {:>10} steps {:>12.3?} {:>8.1} Minstr/s", name, steps, best, rate);
+}
+
+/// Runs the program in BOOST sensor boost mode (input 2) on a few machine
+/// configurations.
+fn bench(program: Vec<ValueType>, runs: usize) {
+    bench_case("paged", &IntComputer::new(program.clone()), 2, runs);
+    bench_case("vec", &IntComputer::with_memory(program.clone()), 2, runs);
+
+    let wide: Vec<i128> = program.iter().map(|&x| x.into()).collect();
+    let wide = IntComputer::with_memory(PagedMemory::new(wide));
+    bench_case("i128", &wide, 2, runs);
+
+    let mut history = IntComputer::new(program);
+    history.enable_history(1 << 16);
+    bench_case("history", &history, 2, runs);
+}
+
+/// Runs the program and prints which addresses were used as code and data.
+fn cover(program: Vec<ValueType>, input: &[String]) {
+    let len = program.len();
+    instrumented(program, input, IntComputer::enable_coverage, |mut c| {
+        print!("{}", c.take_coverage().unwrap().heatmap(len, 64));
+        println!("#: instruction  +: parameter  r/w/*: read/written/both  !: code written  .: unused");
+    });
+}
+
+/// Runs the program and prints where it spent its time.
+fn profile(program: Vec<ValueType>, input: &[String]) {
+    instrumented(program, input, IntComputer::enable_profiling, |mut c| {
+        print!("{}", c.take_profile().unwrap().report(20));
+    });
+}
+
+fn debug(program: Vec<ValueType>) {
+    let mut debugger = Debugger::new(IntComputer::new(program));
+    println!("{}", debugger.current());
+    let stdin = io::stdin();
+    loop {
+        print!("(dbg) ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        match debugger.execute(&line) {
+            Some(text) if text.is_empty() => (),
+            Some(text) => println!("{}", text),
+            None => break,
+        }
+    }
+}
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let file = args.get(1).map_or("puzzle_input.txt", String::as_str);
+    match args.first().map(String::as_str) {
+        None => (),
+        Some("disasm") => {
+            print!("{}", disassemble(&load_program(file)));
+            return;
+        }
+        Some("cfg") => {
+            print!("{}", control_flow(&load_program(file)).to_dot());
+            return;
+        }
+        Some("debug") => {
+            debug(load_program(file));
+            return;
+        }
+        Some("trace") => {
+            trace(load_program(file), args.get(2..).unwrap_or(&[]));
+            return;
+        }
+        Some("cover") => {
+            cover(load_program(file), args.get(2..).unwrap_or(&[]));
+            return;
+        }
+        Some("profile") => {
+            profile(load_program(file), args.get(2..).unwrap_or(&[]));
+            return;
+        }
+        Some("bench") => {
+            let runs = match args.get(2).map_or(Ok(10), |n| n.parse()) {
+                Ok(0) | Err(_) => usage(),
+                Ok(runs) => runs,
+            };
+            bench(load_program(file), runs);
+            return;
+        }
+        Some(_) => usage(),
+    }
+
     println!("------------ Day 9 ------------");
-    let (_, dur) = timed(move || day_9::day_9_run_part1());
+    let (_, dur) = timed(day_9::day_9_run_part1);
     println!("Time {:?}", dur);
-    let (_, dur) = timed(move || day_9::day_9_run_part2());
+    let (_, dur) = timed(day_9::day_9_run_part2);
     println!("Time {:?}", dur);
 }